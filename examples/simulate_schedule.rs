@@ -0,0 +1,33 @@
+use std::env;
+
+use caminatus::device::simulate;
+use caminatus::schedule::Schedule;
+use caminatus::sensor::simulation::SimulationConfig;
+use caminatus::{ControlStrategy, KilnConfig};
+
+/// Fires a schedule on the simulated kiln and prints the trace as csv, with either controller.
+///   cargo run --example simulate_schedule -- ./schedules/sample.yaml fuzzy
+fn main() {
+    let file = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "./schedules/sample.yaml".to_string());
+    let controller = match env::args().nth(2).as_deref() {
+        Some("fuzzy") => ControlStrategy::Fuzzy,
+        _ => ControlStrategy::Pid,
+    };
+    let schedule = Schedule::from_file(file).unwrap().normalize().unwrap();
+    let config = KilnConfig {
+        controller,
+        ..KilnConfig::default()
+    };
+
+    let trace = simulate(schedule, &config, SimulationConfig::default(), 1000).unwrap();
+
+    println!("runtime,temperature,set_point,output");
+    for point in trace {
+        println!(
+            "{},{:.2},{:.2},{:.3}",
+            point.runtime, point.temperature, point.set_point, point.output
+        );
+    }
+}
@@ -7,10 +7,25 @@ use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
+use crate::device::Gains;
+use crate::firing::ExportFormat;
+use crate::sensor::simulation::SimulationConfig;
+
 pub const DEFAULT_CONFIG_FILE: &str = "./config.yaml";
 pub const DEFAULT_SCHEDULES_FOLDER: &str = "./schedules";
+pub const DEFAULT_FIRINGS_FOLDER: &str = "./firings";
 pub const DEFAULT_LOG_LEVEL: &str = "info";
 pub const DEFAULT_POLL_DURATION: u32 = 1000;
+pub const DEFAULT_RESUME_WINDOW: u32 = 1800;
+pub const DEFAULT_MAX_TEMP: f64 = 1300.0;
+pub const DEFAULT_MAX_DIFFERENCE_TIME: u32 = 1800;
+pub const DEFAULT_PWM_WINDOW: u32 = 2000;
+pub const DEFAULT_AMBIENT: f64 = 25.0;
+pub const DEFAULT_FUZZY_STEP_SIZE: f32 = 10.0;
+pub const DEFAULT_MAX_DIFFERENCE: f32 = 25.0;
+pub const DEFAULT_PROPORTIONAL: f64 = 25.0;
+pub const DEFAULT_INTEGRAL: f64 = 1088.0;
+pub const DEFAULT_DERIVATIVE: f64 = 217.0;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "caminatus")]
@@ -22,27 +37,232 @@ pub struct Opt {
     /// the folder where schedule files will be managed
     #[structopt(short, long, name = "SCHEDULES FOLDER")]
     schedules_folder: Option<String>,
+
+    /// the folder where the history of firings will be recorded
+    #[structopt(short, long, name = "FIRINGS FOLDER")]
+    firings_folder: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
+
+/// Tasks to run instead of starting the kiln controller.
+#[derive(StructOpt, Debug, Clone)]
+pub enum Subcommand {
+    /// writes the samples of a recorded firing as csv or newline delimited json
+    Export {
+        /// id of the firing to export
+        id: String,
+
+        /// either csv or json
+        #[structopt(short, long, default_value = "csv")]
+        format: ExportFormat,
+
+        /// file to write to, instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Where the kiln's thermocouple and heater come from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The MCP9600 and solid state relay wired to the Raspberry Pi.
+    Hardware,
+    /// A thermal model of a kiln, for running without a Raspberry Pi.
+    Simulated,
+}
+
+impl Default for Backend {
+    /// The hardware when running on the Raspberry Pi, and the model anywhere else, so a
+    /// development machine never tries to open i2c or gpio it doesn't have.
+    fn default() -> Self {
+        if cfg!(any(target_arch = "arm", target_arch = "aarch64")) {
+            Backend::Hardware
+        } else {
+            Backend::Simulated
+        }
+    }
+}
+
+/// How the kiln works out the heater's duty cycle.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlStrategy {
+    #[default]
+    Pid,
+    /// Fuzzy rules on the error and its rate of change, scaled by `fuzzy_step_size`.
+    Fuzzy,
 }
 
 #[derive(Debug, Deserialize)]
 struct ConfigFile {
     pub log_level: Option<String>,
     pub schedules_folder: Option<String>,
+    pub firings_folder: Option<String>,
     pub web: WebConfigSection,
     pub poll_interval: Option<u32>,
+    pub backend: Option<Backend>,
     pub thermocouple_address: u16,
     pub gpio: GpioConfig,
     pub kiln: KilnConfig,
+    pub simulation: Option<SimulationConfig>,
+    pub watchdog: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct KilnConfig {
     pub fuzzy_step_size: f32,
     pub max_difference: f32,
+    /// Absolute maximum temperature in celsius, above which the heater is forced off.
+    #[serde(default = "default_max_temp")]
+    pub max_temp: f64,
+    /// Longest time in seconds the kiln can stay more than max_difference below the set point.
+    #[serde(default = "default_max_difference_time")]
+    pub max_difference_time: u32,
     pub proportional: f64,
     pub integral: f64,
     pub derivative: f64,
+    /// Longest time in seconds the kiln can be off and still carry on an interrupted firing from
+    ///   where it stopped, rather than from its measured temperature.
+    #[serde(default = "default_resume_window")]
+    pub resume_window: u32,
+    /// Milliseconds of each heater on/off cycle, which is independent of the poll interval.
+    #[serde(default = "default_pwm_window")]
+    pub pwm_window: u32,
+    /// Shortest time in milliseconds the heater is switched on for.
+    #[serde(default)]
+    pub min_on_time: u32,
+    /// Shortest time in milliseconds the heater is switched off for.
+    #[serde(default)]
+    pub min_off_time: u32,
+    #[serde(default)]
+    pub controller: ControlStrategy,
+    #[serde(default)]
+    pub feed_forward: FeedForwardConfig,
+    /// Gains for the PID at different temperatures, in place of the ones above. In between bands
+    /// the gains are interpolated.
+    #[serde(default)]
+    pub gain_schedule: Vec<GainBand>,
+}
+
+impl Default for KilnConfig {
+    fn default() -> Self {
+        KilnConfig {
+            fuzzy_step_size: DEFAULT_FUZZY_STEP_SIZE,
+            max_difference: DEFAULT_MAX_DIFFERENCE,
+            max_temp: DEFAULT_MAX_TEMP,
+            max_difference_time: DEFAULT_MAX_DIFFERENCE_TIME,
+            proportional: DEFAULT_PROPORTIONAL,
+            integral: DEFAULT_INTEGRAL,
+            derivative: DEFAULT_DERIVATIVE,
+            resume_window: DEFAULT_RESUME_WINDOW,
+            pwm_window: DEFAULT_PWM_WINDOW,
+            min_on_time: 0,
+            min_off_time: 0,
+            controller: ControlStrategy::default(),
+            feed_forward: FeedForwardConfig::default(),
+            gain_schedule: Vec::new(),
+        }
+    }
+}
+
+impl KilnConfig {
+    /// Checks the settings the kiln can't run with.
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.fuzzy_step_size.is_nan() || self.fuzzy_step_size <= 0.0 {
+            return Err(ConfigError::ParseError(
+                "fuzzy_step_size must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.pwm_window == 0 {
+            return Err(ConfigError::ParseError(
+                "pwm_window must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.min_on_time >= self.pwm_window {
+            return Err(ConfigError::ParseError(format!(
+                "min_on_time must be shorter than the pwm_window of {}ms",
+                self.pwm_window
+            )));
+        }
+
+        if self.min_off_time >= self.pwm_window {
+            return Err(ConfigError::ParseError(format!(
+                "min_off_time must be shorter than the pwm_window of {}ms",
+                self.pwm_window
+            )));
+        }
+
+        // Any longer and there's no duty cycle the heater can be switched both on and off for.
+        if self.min_on_time as u64 + self.min_off_time as u64 > self.pwm_window as u64 {
+            return Err(ConfigError::ParseError(format!(
+                "min_on_time and min_off_time must fit in the pwm_window of {}ms",
+                self.pwm_window
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// PID gains for the kiln at a temperature, in celsius.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GainBand {
+    pub temperature: f64,
+    #[serde(flatten)]
+    pub gains: Gains,
+}
+
+/// Heat put in ahead of the controller, as a share of the heater's duty cycle, for what the
+/// schedule is about to ask of the kiln. Off unless set.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeedForwardConfig {
+    /// Duty cycle for each degree per hour the schedule climbs.
+    #[serde(default)]
+    pub ramp: f64,
+    /// Duty cycle for each degree the kiln is above ambient, making up for what's lost through the
+    /// walls.
+    #[serde(default)]
+    pub loss: f64,
+    /// Temperature of the room the kiln sits in, in celsius.
+    #[serde(default = "default_ambient")]
+    pub ambient: f64,
+}
+
+impl Default for FeedForwardConfig {
+    fn default() -> Self {
+        FeedForwardConfig {
+            ramp: 0.0,
+            loss: 0.0,
+            ambient: DEFAULT_AMBIENT,
+        }
+    }
+}
+
+fn default_max_temp() -> f64 {
+    DEFAULT_MAX_TEMP
+}
+
+fn default_max_difference_time() -> u32 {
+    DEFAULT_MAX_DIFFERENCE_TIME
 }
+
+fn default_resume_window() -> u32 {
+    DEFAULT_RESUME_WINDOW
+}
+
+fn default_pwm_window() -> u32 {
+    DEFAULT_PWM_WINDOW
+}
+
+fn default_ambient() -> f64 {
+    DEFAULT_AMBIENT
+}
+
 #[derive(Debug, Deserialize)]
 struct WebConfigSection {
     pub port: u16,
@@ -59,11 +279,19 @@ pub struct GpioConfig {
 pub struct Config {
     pub log_level: String,
     pub schedules_folder: String,
+    pub firings_folder: String,
     pub web: WebConfig,
     pub poll_interval: u32,
+    pub backend: Backend,
     pub thermocouple_address: u16,
     pub gpio: GpioConfig,
     pub kiln: KilnConfig,
+    pub simulation: SimulationConfig,
+    /// Watchdog device the kiln loop keeps alive, when enabled.
+    pub watchdog: Option<String>,
+    pub command: Option<Subcommand>,
+    /// Where the config was read from, so autotuned gains can be written back.
+    pub config_file: PathBuf,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -95,10 +323,12 @@ impl Config {
     pub fn with_cli(self, options: Opt) -> Result<Config, ConfigError> {
         let schedules_folder = options.schedules_folder.unwrap_or(self.schedules_folder);
         let schedules_folder = validate_directory(schedules_folder)?;
+        let firings_folder = options.firings_folder.unwrap_or(self.firings_folder);
 
         let conf = Config {
             log_level: self.log_level,
             schedules_folder,
+            firings_folder,
             web: WebConfig {
                 port: self.web.port,
                 host_ip: self.web.host_ip,
@@ -108,20 +338,78 @@ impl Config {
                 heater: self.gpio.heater,
             },
             poll_interval: self.poll_interval,
+            backend: self.backend,
             thermocouple_address: self.thermocouple_address,
-            kiln: KilnConfig {
-                fuzzy_step_size: self.kiln.fuzzy_step_size,
-                max_difference: self.kiln.max_difference,
-                proportional: self.kiln.proportional,
-                integral: self.kiln.integral,
-                derivative: self.kiln.derivative,
-            },
+            kiln: self.kiln,
+            simulation: self.simulation,
+            watchdog: self.watchdog,
+            command: options.command,
+            config_file: options.config_file.unwrap_or(self.config_file),
         };
 
         Ok(conf)
     }
 }
 
+/// Writes the gains over the `proportional`, `integral` and `derivative` values in the config
+/// file, leaving the rest of it, comments included, as it was. Gains further in, in the gain
+/// schedule, are left alone too.
+pub fn save_gains(config_file: &Path, gains: &Gains) -> Result<(), ConfigError> {
+    let content = fs::read_to_string(config_file)?;
+    let values = [
+        ("proportional", gains.proportional),
+        ("integral", gains.integral),
+        ("derivative", gains.derivative),
+    ];
+    let value = |line: &str| {
+        let setting = line.trim_start();
+        values
+            .iter()
+            .find(|(name, _)| setting.starts_with(&format!("{}:", name)))
+    };
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let outermost = content
+        .lines()
+        .filter(|line| value(line).is_some())
+        .map(indent)
+        .min();
+    let mut replaced = 0;
+
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let setting = line.trim_start();
+
+            match value(line).filter(|_| Some(indent(line)) == outermost) {
+                Some((name, value)) => {
+                    replaced += 1;
+                    let indent = &line[..line.len() - setting.len()];
+                    let comment = setting.find(" #").map_or("", |i| &setting[i..]);
+                    format!("{}{}: {}{}", indent, name, value, comment)
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect();
+
+    if replaced != values.len() {
+        return Err(ConfigError::ParseError(format!(
+            "expected one each of proportional, integral and derivative in {}",
+            config_file.display()
+        )));
+    }
+
+    if content.ends_with('\n') {
+        lines.push(String::new());
+    }
+
+    let partial = config_file.with_extension("partial");
+    fs::write(&partial, lines.join("\n"))?;
+    fs::rename(&partial, config_file)?;
+
+    Ok(())
+}
+
 fn validate_directory(dir: String) -> Result<String, ConfigError> {
     let folder = Path::new(&dir);
 
@@ -166,12 +454,16 @@ impl TryFrom<ConfigFile> for Config {
 
     fn try_from(value: ConfigFile) -> Result<Self, Self::Error> {
         let host_ip: Ipv4Addr = value.web.host_ip.parse()?;
+        value.kiln.validate()?;
 
         let conf = Config {
             log_level: value.log_level.unwrap_or(DEFAULT_LOG_LEVEL.to_string()),
             schedules_folder: value
                 .schedules_folder
                 .unwrap_or(DEFAULT_SCHEDULES_FOLDER.to_string()),
+            firings_folder: value
+                .firings_folder
+                .unwrap_or(DEFAULT_FIRINGS_FOLDER.to_string()),
             web: WebConfig {
                 port: value.web.port,
                 host_ip,
@@ -181,14 +473,13 @@ impl TryFrom<ConfigFile> for Config {
                 heater: value.gpio.heater,
             },
             poll_interval: value.poll_interval.unwrap_or(DEFAULT_POLL_DURATION), // TODO: enforce value greater than 0
+            backend: value.backend.unwrap_or_default(),
             thermocouple_address: value.thermocouple_address,
-            kiln: KilnConfig {
-                fuzzy_step_size: value.kiln.fuzzy_step_size,
-                max_difference: value.kiln.max_difference,
-                proportional: value.kiln.proportional,
-                integral: value.kiln.integral,
-                derivative: value.kiln.derivative,
-            },
+            kiln: value.kiln,
+            simulation: value.simulation.unwrap_or_default(),
+            watchdog: value.watchdog,
+            command: None,
+            config_file: PathBuf::from(DEFAULT_CONFIG_FILE),
         };
 
         Ok(conf)
@@ -212,3 +503,131 @@ impl From<std::net::AddrParseError> for ConfigError {
         ConfigError::ParseError(format!("ip parsing error: [{}]", error))
     }
 }
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn should_save_gains() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.yaml");
+        fs::copy("./config.yaml.example", &config_file)?;
+
+        let gains = Gains {
+            proportional: 0.04,
+            integral: 0.0001,
+            derivative: 2.5,
+        };
+        save_gains(&config_file, &gains)?;
+
+        let content = fs::read_to_string(&config_file)?;
+        let saved: ConfigFile = serde_yaml::from_str(content.as_str())?;
+        assert_eq!(saved.kiln.proportional, 0.04);
+        assert_eq!(saved.kiln.integral, 0.0001);
+        assert_eq!(saved.kiln.derivative, 2.5);
+        assert_eq!(saved.kiln.max_temp, 1300.0);
+        assert!(content.contains("# How often the thermocouple is read"));
+
+        // Leaves the gains in the schedule as they were.
+        let scheduled = content
+            .replace("  # gain_schedule:", "  gain_schedule:")
+            .replace("  #   - ", "    - ")
+            .replace("  #     ", "      ");
+        let gains = Gains {
+            derivative: 3.0,
+            ..gains
+        };
+        fs::write(&config_file, scheduled)?;
+        save_gains(&config_file, &gains)?;
+        let content = fs::read_to_string(&config_file)?;
+        let saved: ConfigFile = serde_yaml::from_str(content.as_str())?;
+        assert_eq!(saved.kiln.derivative, 3.0);
+        assert_eq!(saved.kiln.gain_schedule.len(), 2);
+        assert_eq!(saved.kiln.gain_schedule[0].gains.derivative, 150.0);
+
+        // Nothing to write over.
+        fs::write(&config_file, "kiln:\n  proportional: 1.0\n")?;
+        assert!(save_gains(&config_file, &gains).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_only_default_to_the_hardware_on_the_pi() -> anyhow::Result<()> {
+        let content = fs::read_to_string("./config.yaml.example")?;
+        let file: ConfigFile = serde_yaml::from_str(&content)?;
+        let config = Config::try_from(file)?;
+
+        if !cfg!(any(target_arch = "arm", target_arch = "aarch64")) {
+            assert_eq!(config.backend, Backend::Simulated);
+        }
+        assert_eq!(config.backend, Backend::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_a_pwm_window_the_heater_cannot_switch_in() -> anyhow::Result<()> {
+        let content = fs::read_to_string("./config.yaml.example")?
+            .replace("pwm_window: 2000", "pwm_window: 0");
+        let file: ConfigFile = serde_yaml::from_str(&content)?;
+        assert!(Config::try_from(file).is_err());
+
+        let pwm = |window, min_on_time, min_off_time| KilnConfig {
+            pwm_window: window,
+            min_on_time,
+            min_off_time,
+            ..KilnConfig::default()
+        };
+        assert!(pwm(2000, 2000, 0).validate().is_err());
+        assert!(pwm(2000, 0, 2000).validate().is_err());
+        assert!(pwm(2000, 1200, 1000).validate().is_err());
+        assert!(pwm(2000, 500, 0).validate().is_ok());
+        assert!(pwm(2000, 1000, 1000).validate().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_a_fuzzy_step_size_that_is_not_positive() {
+        let fuzzy = |fuzzy_step_size| KilnConfig {
+            fuzzy_step_size,
+            ..KilnConfig::default()
+        };
+
+        assert!(fuzzy(0.0).validate().is_err());
+        assert!(fuzzy(-10.0).validate().is_err());
+        assert!(fuzzy(f32::NAN).validate().is_err());
+        assert!(fuzzy(10.0).validate().is_ok());
+    }
+
+    #[test]
+    fn should_read_a_gain_schedule() -> anyhow::Result<()> {
+        let kiln: KilnConfig = serde_yaml::from_str(
+            r#"
+            fuzzy_step_size: 10.0
+            max_difference: 25
+            proportional: 0.05
+            integral: 0.0002
+            derivative: 2.0
+            gain_schedule:
+              - temperature: 100
+                proportional: 0.08
+                integral: 0.0004
+                derivative: 1.0
+              - temperature: 1000
+                proportional: 0.03
+                integral: 0.0001
+                derivative: 4.0
+            "#,
+        )?;
+
+        assert_eq!(kiln.gain_schedule.len(), 2);
+        assert_eq!(kiln.gain_schedule[1].temperature, 1000.0);
+        assert_eq!(kiln.gain_schedule[1].gains.derivative, 4.0);
+
+        Ok(())
+    }
+}
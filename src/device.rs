@@ -1,2 +1,8 @@
 mod kiln;
-pub use kiln::{Kiln, KilnError, KilnEvent, KilnUpdate};
+pub use kiln::{
+    simulate, simulate_autotune, Gains, Kiln, KilnError, KilnEvent, KilnUpdate, PauseMode,
+    StartFrom, TracePoint, Warning,
+};
+
+mod watchdog;
+pub use watchdog::Watchdog;
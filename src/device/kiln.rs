@@ -4,49 +4,670 @@ use std::time::Duration;
 
 use anyhow::Result;
 
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use serde_json;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio::task;
 use tokio::time::sleep;
 use tracing::{error, info, instrument, trace, warn};
 
+mod autotune;
 mod controller;
+mod driver;
+mod safety;
+mod simulator;
+pub use autotune::Gains;
+pub use safety::Fault;
+pub use simulator::{simulate, simulate_autotune, TracePoint};
 
-use crate::config::KilnConfig;
-use crate::schedule::NormalizedSchedule;
-use crate::sensor::{Heater, MCP9600};
+use crate::config::{ControlStrategy, KilnConfig};
+use crate::device::Watchdog;
+use crate::firing::{
+    Checkpoint, CompletionReason, EventKind, Firing, PendingStart, Recorder, Sample,
+};
+use crate::schedule::{NormalizedSchedule, StepKind};
+use crate::sensor::{HeaterOutput, TemperatureSensor};
 use crate::server::Command;
-use controller::{Fuzzy, PID};
+use autotune::{Autotune, TUNING_LIMIT};
+use controller::Terms;
+use driver::{HeaterDriver, Pwm};
+use safety::Safety;
+
+/// Degrees above the set point a kiln cooling with the heater off can be, before it's reported as
+/// unable to cool as fast as the schedule.
+const COOLING_MARGIN: f64 = 5.0;
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize)]
 pub enum KilnState {
     Idle,
     Running,
+    /// Running a schedule with its clock stopped.
+    Paused,
+    /// Shut down by the safety interlock. The heater is held off until the kiln is stopped.
+    Fault,
+    /// Holding a set point that was typed in, rather than following a schedule.
+    Manual,
+    /// Waiting for the time a schedule is due to start.
+    Waiting,
+    /// Switching the heater on and off around a temperature to work out the PID gains.
+    Tuning,
+}
+
+/// Something the kiln can't keep up with, reported for as long as it lasts without stopping the
+/// firing. Temperatures are in C.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum Warning {
+    /// The kiln cools slower than the schedule asks for even with the heater off, and is
+    /// `difference` above the set point.
+    CannotCoolFastEnough { difference: f64 },
+}
+
+/// What the heater does while a schedule is paused.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PauseMode {
+    /// Keeps the kiln at the set point it was paused at.
+    #[default]
+    Hold,
+    /// Turns the heater off, letting the kiln cool.
+    Off,
+}
+
+/// Where in the schedule a new firing starts.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartFrom {
+    /// At the start of the first step.
+    #[default]
+    Beginning,
+    /// At the first point the schedule climbs through the kiln's measured temperature, for a kiln
+    /// that's still warm.
+    Temperature,
 }
 
 #[derive(Debug)]
 pub struct RunState {
     runtime: u32,
+    remainder: u32,
     schedule: Option<NormalizedSchedule>,
     state: KilnState,
+    fault: Option<Fault>,
+    pause: Option<PauseMode>,
+    /// Set point held while in manual mode.
+    manual_set_point: Option<f64>,
+    /// Seconds to stay in manual mode, if it doesn't go on until stopped.
+    timeout: Option<u32>,
+    /// Schedule to start once its time comes, while waiting.
+    waiting: Option<PendingStart>,
+    /// Relay cycling the heater, while tuning.
+    tuning: Option<Autotune>,
+    /// Whether the kiln has come up to the manual set point or tuning target since it was set.
+    reached: bool,
+}
+
+impl Default for RunState {
+    fn default() -> Self {
+        RunState {
+            runtime: 0,
+            remainder: 0,
+            schedule: None,
+            state: KilnState::Idle,
+            fault: None,
+            pause: None,
+            manual_set_point: None,
+            timeout: None,
+            waiting: None,
+            tuning: None,
+            reached: false,
+        }
+    }
+}
+
+impl RunState {
+    /// Starts running the schedule, unless one is already running, in place of any schedule waiting
+    /// to start. Returns true if started.
+    fn start(&mut self, schedule: NormalizedSchedule) -> bool {
+        if self.firing() {
+            error!("attempting to start a schedule while a schedule is already running");
+            false
+        } else if self.state == KilnState::Fault {
+            error!("attempting to start a schedule while the kiln is faulted, stop it first");
+            false
+        } else {
+            info!(name = schedule.name.as_str(), message = "starting schedule");
+            self.state = KilnState::Running;
+            self.runtime = 0;
+            self.remainder = 0;
+            self.schedule = Some(schedule);
+            self.manual_set_point = None;
+            self.timeout = None;
+            self.waiting = None;
+            self.tuning = None;
+            true
+        }
+    }
+
+    /// Holds the kiln at `set_point`, for `timeout` seconds or until stopped, unless a schedule is
+    /// running, in place of any schedule waiting to start. A kiln already in manual mode moves to
+    /// the new set point and starts its clock over. Returns true if holding.
+    fn hold(&mut self, set_point: f64, timeout: Option<u32>) -> bool {
+        if self.firing() {
+            error!("attempting to hold a set point while a schedule is running");
+            false
+        } else if self.state == KilnState::Fault {
+            error!("attempting to hold a set point while the kiln is faulted, stop it first");
+            false
+        } else {
+            info!(set_point, ?timeout, message = "holding set point");
+            self.state = KilnState::Manual;
+            self.runtime = 0;
+            self.remainder = 0;
+            self.manual_set_point = Some(set_point);
+            self.timeout = timeout;
+            self.waiting = None;
+            self.tuning = None;
+            self.reached = false;
+            true
+        }
+    }
+
+    /// Relay cycles the heater around `target` to work out the PID gains, from an idle kiln.
+    /// Returns true if tuning.
+    fn tune(&mut self, target: f64) -> bool {
+        if self.state != KilnState::Idle {
+            error!("attempting to autotune while the kiln is busy, stop it first");
+            false
+        } else {
+            info!(target, message = "autotuning");
+            self.state = KilnState::Tuning;
+            self.tuning = Some(Autotune::new(target));
+            self.reached = false;
+            true
+        }
+    }
+
+    /// Heater duty cycle from the autotune relay, after `interval` milliseconds at `temperature`.
+    fn relay(&mut self, temperature: f64, interval: u32) -> f64 {
+        match self.tuning.as_mut() {
+            Some(tuning) => tuning.step(temperature, interval as f64 / 1000.0),
+            None => 0.0,
+        }
+    }
+
+    /// The gains autotuning came up with, once it has, leaving the kiln idle.
+    fn tuned(&mut self) -> Option<Gains> {
+        let gains = self.tuning.as_ref()?.gains()?;
+
+        info!(?gains, message = "autotune finished");
+        self.state = KilnState::Idle;
+        self.tuning = None;
+        Some(gains)
+    }
+
+    /// Waits to start a schedule, from an idle kiln or in place of the schedule already waiting.
+    /// Returns true if waiting.
+    fn wait(&mut self, pending: PendingStart) -> bool {
+        if self.state != KilnState::Idle && self.state != KilnState::Waiting {
+            error!("attempting to wait for a schedule while the kiln is busy, stop it first");
+            false
+        } else {
+            info!(
+                name = pending.schedule.name.as_str(),
+                at = %pending.at,
+                message = "waiting to start schedule"
+            );
+            self.state = KilnState::Waiting;
+            self.waiting = Some(pending);
+            true
+        }
+    }
+
+    /// Hands over the schedule that's been waiting, once it's due at `now`, leaving the kiln idle
+    /// for it to be started.
+    fn due(&mut self, now: DateTime<Utc>) -> Option<PendingStart> {
+        match &self.waiting {
+            Some(pending) if self.state == KilnState::Waiting && pending.at <= now => {
+                self.state = KilnState::Idle;
+                self.waiting.take()
+            }
+            _ => None,
+        }
+    }
+
+    /// Seconds until the waiting schedule starts.
+    fn countdown(&self, now: DateTime<Utc>) -> Option<u32> {
+        match &self.waiting {
+            Some(pending) if self.state == KilnState::Waiting => {
+                Some((pending.at - now).num_seconds().max(0) as u32)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves a schedule that's just been started up to the first point where it climbs through
+    /// `temperature`, staying at the start if it never does.
+    fn fast_forward(&mut self, temperature: f64) {
+        if let Some(schedule) = &self.schedule {
+            if let Some(runtime) = schedule.first_time_at_temperature(temperature) {
+                info!(
+                    temperature,
+                    runtime,
+                    message = "starting from measured temperature"
+                );
+                self.runtime = runtime;
+            }
+        }
+    }
+
+    /// Picks an interrupted schedule back up, `runtime` seconds in.
+    fn restore(&mut self, schedule: NormalizedSchedule, runtime: u32) {
+        info!(
+            name = schedule.name.as_str(),
+            runtime,
+            message = "restoring schedule"
+        );
+        self.state = KilnState::Running;
+        self.runtime = runtime;
+        self.remainder = 0;
+        self.schedule = Some(schedule);
+    }
+
+    /// Stops the kiln, clearing any fault.
+    fn stop(&mut self) {
+        if self.state == KilnState::Idle {
+            warn!("attempting to stop already idle kiln");
+        }
+
+        self.state = KilnState::Idle;
+        self.runtime = 0;
+        self.remainder = 0;
+        self.schedule = None;
+        self.fault = None;
+        self.pause = None;
+        self.manual_set_point = None;
+        self.timeout = None;
+        self.waiting = None;
+        self.tuning = None;
+    }
+
+    /// Stops the schedule's clock. Returns true if paused.
+    fn pause(&mut self, mode: PauseMode) -> bool {
+        if self.state == KilnState::Running {
+            info!(?mode, message = "pausing schedule");
+            self.state = KilnState::Paused;
+            self.pause = Some(mode);
+            true
+        } else {
+            warn!("attempting to pause a kiln that isn't running");
+            false
+        }
+    }
+
+    /// Starts the paused schedule's clock again. Returns true if resumed.
+    fn resume(&mut self) -> bool {
+        if self.state == KilnState::Paused {
+            info!("resuming schedule");
+            self.state = KilnState::Running;
+            self.pause = None;
+            true
+        } else {
+            warn!("attempting to resume a kiln that isn't paused");
+            false
+        }
+    }
+
+    /// Moves the schedule's clock to the start of the given step, or of the step after the current
+    /// one. Skipping past the last step completes the schedule. Returns the steps moved from and to.
+    fn skip(&mut self, step: Option<usize>) -> Option<(usize, usize)> {
+        let schedule = match &self.schedule {
+            Some(schedule) if self.firing() => schedule,
+            _ => {
+                warn!("attempting to skip steps while no schedule is running");
+                return None;
+            }
+        };
+        let from = schedule.step_index(self.runtime)?;
+        let to = step.unwrap_or(from + 1);
+
+        let runtime = match schedule.steps.get(to) {
+            Some(next) => next.start_time,
+            None if step.is_none() => schedule.total_duration(),
+            None => {
+                warn!(step = to, "attempting to skip to a missing step");
+                return None;
+            }
+        };
+
+        info!(from, to, message = "skipping steps");
+        self.runtime = runtime;
+        self.remainder = 0;
+        Some((from, to))
+    }
+
+    /// Replaces what's left of the running schedule from its clock on, keeping the clock where it
+    /// is. Returns the index of the first new step.
+    fn edit(&mut self, steps: &[String]) -> Option<usize> {
+        if !self.firing() {
+            warn!("attempting to edit steps while no schedule is running");
+            return None;
+        }
+
+        let schedule = self.schedule.as_mut()?;
+        match schedule.replace_remaining(self.runtime, steps) {
+            Ok(from) => {
+                info!(from, message = "editing schedule");
+                Some(from)
+            }
+            Err(error) => {
+                warn!("unable to edit schedule: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Whether a schedule is being fired, paused or not.
+    fn firing(&self) -> bool {
+        self.state == KilnState::Running || self.state == KilnState::Paused
+    }
+
+    /// Whether the heater should be driven towards the set point.
+    fn heating(&self) -> bool {
+        self.state == KilnState::Running
+            || self.state == KilnState::Manual
+            || self.pause == Some(PauseMode::Hold)
+    }
+
+    /// The set point the safety checks hold the kiln to at `temperature`, while the heater is being
+    /// driven towards it or tuned around it. Nothing paces the climb to a manual set point or
+    /// tuning target, so the kiln is only held to it once it has first come up to it.
+    fn target(&mut self, temperature: f64) -> Option<f64> {
+        let set_point = self.set_point();
+        if self.state == KilnState::Manual || self.state == KilnState::Tuning {
+            self.reached |= temperature >= set_point;
+        }
+
+        match self.state {
+            KilnState::Manual | KilnState::Tuning if !self.reached => None,
+            KilnState::Tuning => Some(set_point),
+            _ if self.heating() => Some(set_point),
+            _ => None,
+        }
+    }
+
+    /// The fault for autotuning that has gone on too long without settling.
+    fn overdue(&self) -> Option<Fault> {
+        match &self.tuning {
+            Some(tuning) if tuning.expired() => Some(Fault::TuningTimeout {
+                target: tuning.target(),
+                seconds: TUNING_LIMIT,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Abandons the schedule for the fault, keeping the runtime it got to.
+    fn trip(&mut self, fault: Fault) {
+        error!(?fault, message = "safety interlock tripped, shutting down");
+        self.state = KilnState::Fault;
+        self.schedule = None;
+        self.fault = Some(fault);
+        self.pause = None;
+        self.waiting = None;
+        self.tuning = None;
+    }
+
+    /// The manual set point, the schedule's set point at the current runtime, or the temperature
+    /// being tuned around.
+    fn set_point(&self) -> f64 {
+        match (self.manual_set_point, &self.schedule, &self.tuning) {
+            (Some(set_point), _, _) => set_point,
+            (None, Some(schedule), _) => schedule.target_temperature(self.runtime),
+            (None, None, Some(tuning)) => tuning.target(),
+            (None, None, None) => 0.0,
+        }
+    }
+
+    /// How quickly the set point is climbing, in degrees per second. Nothing unless the schedule's
+    /// clock is running.
+    fn ramp_rate(&self) -> f64 {
+        match &self.schedule {
+            Some(schedule) if self.state == KilnState::Running => schedule.ramp_rate(self.runtime),
+            _ => 0.0,
+        }
+    }
+
+    /// Whether the schedule's clock is running through a step cooling at a controlled rate.
+    fn cooling(&self) -> bool {
+        match &self.schedule {
+            Some(schedule) if self.state == KilnState::Running => matches!(
+                schedule.step_index(self.runtime),
+                Some(index) if schedule.steps[index].cooling()
+            ),
+            _ => false,
+        }
+    }
+
+    /// What the kiln can't keep up with at `temperature`, with the heater given `duty`.
+    fn warning(&self, temperature: f64, duty: f64) -> Option<Warning> {
+        let difference = temperature - self.set_point();
+
+        if self.cooling() && duty <= 0.0 && difference > COOLING_MARGIN {
+            Some(Warning::CannotCoolFastEnough { difference })
+        } else {
+            None
+        }
+    }
+
+    /// Index and kind of the step the schedule's clock is in, while firing.
+    fn step(&self) -> Option<(usize, StepKind)> {
+        let schedule = self.schedule.as_ref().filter(|_| self.firing())?;
+        let index = schedule.step_index(self.runtime)?;
+
+        Some((index, schedule.steps[index].kind()))
+    }
+
+    /// Seconds left of the schedule, or of a manual set point with a timeout.
+    fn remaining(&self) -> Option<u32> {
+        match (&self.schedule, self.timeout) {
+            (Some(schedule), _) if self.firing() => {
+                Some(schedule.total_duration().saturating_sub(self.runtime))
+            }
+            (None, Some(timeout)) if self.state == KilnState::Manual => {
+                Some(timeout.saturating_sub(self.runtime))
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves the schedule, or the time spent in manual mode, forward by `interval` milliseconds,
+    /// unless paused. Returns true once the schedule is complete or manual mode has timed out.
+    fn advance(&mut self, interval: u32) -> bool {
+        if self.state != KilnState::Running && self.state != KilnState::Manual {
+            return false;
+        }
+
+        self.remainder += interval;
+        self.runtime += self.remainder / 1000;
+        self.remainder %= 1000;
+
+        if self.state == KilnState::Manual {
+            return matches!(self.timeout, Some(timeout) if self.runtime >= timeout);
+        }
+
+        match &self.schedule {
+            Some(schedule) => self.runtime > schedule.total_duration(),
+            None => false,
+        }
+    }
+}
+
+/// Runtime to resume an interrupted firing from. After a short interruption the firing carries on
+/// from its last checkpoint. After one longer than `window` seconds the kiln has cooled, so the
+/// firing goes back to where the schedule last climbed through the measured temperature. Once the
+/// schedule is past its peak, a cooling step picks up where it passes through the measured
+/// temperature and anything else where it left off, rather than firing the ware all over again.
+fn resume_point(
+    schedule: &NormalizedSchedule,
+    checkpoint: &Checkpoint,
+    temperature: f64,
+    window: u32,
+) -> u32 {
+    let offline = (Utc::now() - checkpoint.timestamp).num_seconds();
+
+    if offline <= window as i64 || temperature >= schedule.target_temperature(checkpoint.runtime) {
+        return checkpoint.runtime;
+    }
+
+    let index = schedule.step_index(checkpoint.runtime).unwrap_or(0);
+    let heats_again = schedule.steps[index..]
+        .iter()
+        .any(|step| step.kind() == StepKind::Heat);
+
+    match schedule.steps.get(index) {
+        Some(step) if step.kind() == StepKind::Cool => {
+            if temperature >= step.end_temperature {
+                let fraction = (step.start_temperature - temperature)
+                    / (step.start_temperature - step.end_temperature);
+
+                step.start_time + (fraction * (step.end_time - step.start_time) as f64) as u32
+            } else {
+                checkpoint.runtime
+            }
+        }
+        Some(_) if !heats_again => checkpoint.runtime,
+        _ => schedule
+            .time_at_temperature(temperature, checkpoint.runtime)
+            .unwrap_or(0),
+    }
+}
+
+fn log_event(recorder: &mut Recorder, runtime: u32, kind: EventKind) {
+    if let Err(error) = recorder.log(runtime, kind) {
+        error!("unable to log firing event: {}", error);
+    }
+}
+
+/// What one poll of the kiln loop decided, where
+/// set_point: in C
+/// output: controller output, 0 when the controller didn't run
+/// duty: share of the poll the heater is to be on, between 0 and 1
+/// driving: whether the heater was driven towards the schedule, so its clock moves on
+/// warning: what the kiln can't keep up with
+/// tuned: gains autotuning came up with this poll
+#[derive(Debug)]
+struct Poll {
+    set_point: f64,
+    output: f64,
+    duty: f64,
+    driving: bool,
+    warning: Option<Warning>,
+    tuned: Option<Gains>,
+}
+
+/// Works out the heater's duty for one poll from the `temperature` read, if any. `delta` is the
+/// time since the last poll in seconds, or `None` to let the controller go by the wall clock.
+fn control(
+    run: &mut RunState,
+    controller: &mut dyn controller::Controller,
+    config: &KilnConfig,
+    temperature: Option<f64>,
+    interval: u32,
+    delta: Option<f64>,
+) -> Poll {
+    let set_point = run.set_point();
+    let mut output = 0.0;
+
+    let duty = match (run.state, temperature) {
+        (_, None) if run.heating() => {
+            warn!("no temperature this poll, holding the heater off");
+            0.0
+        }
+        (_, Some(temperature)) if run.heating() => {
+            let ahead =
+                controller::feed_forward(&config.feed_forward, run.ramp_rate(), temperature);
+            output = match delta {
+                Some(delta) => controller.compute_with_delta(set_point, temperature, ahead, delta),
+                None => controller.compute(set_point, temperature, ahead),
+            };
+
+            info!("output: {}", output);
+            output
+        }
+        (KilnState::Tuning, Some(temperature)) => run.relay(temperature, interval),
+        _ => {
+            controller.idle();
+            0.0
+        }
+    };
+
+    Poll {
+        set_point,
+        output,
+        duty,
+        driving: run.heating() && temperature.is_some(),
+        warning: temperature.and_then(|temperature| run.warning(temperature, duty)),
+        tuned: run.tuned(),
+    }
+}
+
+/// Sleeps for `duration`, waking early once a shutdown has been asked for.
+async fn sleep_unless_shutdown(duration: Duration, shutdown: &mut watch::Receiver<bool>) {
+    if *shutdown.borrow() {
+        return;
+    }
+
+    tokio::select! {
+        _ = sleep(duration) => (),
+        _ = shutdown.changed() => (),
+    }
 }
 
 #[derive(Debug)]
 pub enum KilnEvent {
     Complete,
-    Start(NormalizedSchedule),
+    Start(NormalizedSchedule, StartFrom),
     Started,
     Stop,
     Stopped,
     Unchanged,
     Failure(String),
     Update,
+    /// Turns the heater off and ends the control loop, answering once the heater is off.
+    Shutdown(oneshot::Sender<()>),
+    Pause(PauseMode),
+    Resume,
+    /// Jumps to the start of the step with the given index, or of the next step.
+    Skip(Option<usize>),
+    /// Replaces the steps from the current one on.
+    Edit(Vec<String>),
+    /// Holds a set point in C, for a number of seconds or until stopped.
+    Hold {
+        temperature: f64,
+        timeout: Option<u32>,
+    },
+    /// Starts a schedule at a later time.
+    Wait(PendingStart),
+    /// Works out PID gains by relay cycling the heater around a temperature in C.
+    Autotune(f64),
+    /// Switches the PID to the gains autotuning last came up with, and saves them to the config.
+    ApplyGains,
 }
 
 /// State of the kiln, sent to clients where
 /// temperature and set_point: recorded temperature in C
 /// runtime: time the schedule has been running in seconds
+/// countdown: time until a waiting schedule starts in seconds
+/// gains: proposed by the last autotune, until applied
+/// gain_band: temperature in C of the band in the gain schedule the PID is using
+/// cold_junction: temperature in C of the thermocouple amplifier itself
+/// terms: what the PID's proportional, integral and derivative terms add to the output
+/// duty_cycle: share of the time the heater is being asked to be on, between 0 and 1
+/// step and step_kind: index and kind of the schedule step running
+/// remaining: time left of the schedule, or of a manual set point with a timeout, in seconds
+/// schedule: name of the schedule being fired
+/// warning: what the kiln can't keep up with, while it lasts
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct KilnUpdate {
@@ -54,6 +675,18 @@ pub struct KilnUpdate {
     state: KilnState,
     runtime: u32,
     set_point: f64,
+    fault: Option<Fault>,
+    countdown: Option<u32>,
+    gains: Option<Gains>,
+    gain_band: Option<f64>,
+    cold_junction: Option<f64>,
+    terms: Option<Terms>,
+    duty_cycle: f64,
+    step: Option<usize>,
+    step_kind: Option<StepKind>,
+    remaining: Option<u32>,
+    schedule: Option<String>,
+    warning: Option<Warning>,
 }
 
 ///
@@ -66,97 +699,314 @@ pub struct Kiln {
 
 ///
 impl Kiln {
-    #[instrument]
+    /// Starts the control loop, which reads the thermocouple every `interval` milliseconds and
+    /// pets the watchdog, if there is one, every iteration. The heater is handed to a driver task
+    /// that switches it on for a share of every PWM window.
+    #[instrument(skip(thermocouple, heater))]
     pub async fn start(
-        thermocouple_address: u16,
-        heater_pin: u8,
+        mut thermocouple: Box<dyn TemperatureSensor>,
+        heater: Box<dyn HeaterOutput>,
         interval: u32,
         manager_sender: broadcast::Sender<Command>,
         config: KilnConfig,
+        firings_folder: String,
+        mut watchdog: Option<Watchdog>,
     ) -> Result<mpsc::Sender<KilnEvent>> {
         info!("starting kiln");
         let channel = "kiln";
         let update_tx = manager_sender.clone();
         let queue = Arc::new(Mutex::new(VecDeque::<KilnEvent>::new()));
         let (tx, mut rx): (mpsc::Sender<KilnEvent>, mpsc::Receiver<KilnEvent>) = mpsc::channel(8);
+        let (shutdown_tx, mut shutdown) = watch::channel(false);
 
         let update_queue = queue.clone();
         let _updater = task::spawn(async move {
-            let mut thermocouple = MCP9600::new(thermocouple_address).unwrap();
-            let mut heater = Heater::new(heater_pin).unwrap();
-            let mut runtime: u32 = 0;
-            let mut schedule: Option<NormalizedSchedule> = None;
-            let mut state = KilnState::Idle;
-            let mut pid = PID::init(config.integral, config.proportional, config.derivative);
+            let mut run = RunState::default();
+            let mut controller = controller::from_config(&config);
+            let mut recorder = Recorder::new(&firings_folder);
+            let mut safety = Safety::new(&config);
+            let driver = HeaterDriver::start(heater, Pwm::new(&config));
+            let mut last_on_time: u64 = 0;
+            let mut temperature: f64 = 0.0;
+            let mut proposed: Option<Gains> = None;
+            let mut warning: Option<Warning> = None;
+
+            match Firing::unfinished(&firings_folder) {
+                Ok(Some((firing, checkpoint))) => {
+                    let runtime = match thermocouple.read() {
+                        Ok(temperature) => resume_point(
+                            &firing.schedule,
+                            &checkpoint,
+                            temperature,
+                            config.resume_window,
+                        ),
+                        Err(_) => checkpoint.runtime,
+                    };
+
+                    run.restore(firing.schedule.clone(), runtime);
+                    if let Err(error) = recorder.resume(firing) {
+                        error!("unable to record resumed firing: {}", error);
+                    }
+                }
+                Ok(None) => match PendingStart::load(&firings_folder) {
+                    Ok(Some(pending)) => {
+                        run.wait(pending);
+                    }
+                    Ok(None) => (),
+                    Err(error) => error!("unable to look for a waiting schedule: {}", error),
+                },
+                Err(error) => error!("unable to look for an interrupted firing: {}", error),
+            }
 
             loop {
-                let temperature = &thermocouple.read().unwrap();
-                let mut set_point: f64 = 0.0;
-                let maybe_update = {
-                    update_queue
+                let reading = safety.read(thermocouple.as_mut());
+                let maybe_update = match run.due(Utc::now()) {
+                    Some(pending) => {
+                        if let Err(error) = PendingStart::clear(&firings_folder) {
+                            error!("unable to clear waiting schedule: {}", error);
+                        }
+                        Some(KilnEvent::Start(pending.schedule, pending.from))
+                    }
+                    None => update_queue
                         .lock()
                         .expect("unable to lock update queue")
-                        .pop_front()
+                        .pop_front(),
                 };
 
                 match maybe_update {
-                    Some(KilnEvent::Start(s)) => {
-                        if state == KilnState::Running {
-                            error!("attempting to start a schedule while a schedule is already running");
-                        } else {
-                            info!(name = s.name.as_str(), message = "starting schedule");
-                            state = KilnState::Running;
-                            runtime = 0;
-                            schedule = Some(s);
+                    Some(
+                        KilnEvent::Start(s, _) | KilnEvent::Wait(PendingStart { schedule: s, .. }),
+                    ) if s.peak_temperature() > config.max_temp => error!(
+                        peak = s.peak_temperature(),
+                        "refusing to fire a schedule above the maximum temperature"
+                    ),
+                    Some(KilnEvent::Start(s, from)) => {
+                        let started = run.start(s.clone());
+
+                        if started && from == StartFrom::Temperature {
+                            match reading {
+                                Ok(Some(measured)) => run.fast_forward(measured),
+                                _ => warn!("no temperature this poll, starting from the beginning"),
+                            }
+                        }
+
+                        if started {
+                            controller.reset();
+                            if let Err(error) = recorder.start(&s) {
+                                error!("unable to record firing: {}", error);
+                            }
+                            if let Err(error) = PendingStart::clear(&firings_folder) {
+                                error!("unable to clear waiting schedule: {}", error);
+                            }
                         }
                     }
-                    Some(KilnEvent::Stop) | Some(KilnEvent::Complete) => {
-                        if state == KilnState::Idle {
-                            warn!("attempting to stop already idle kiln");
+                    Some(KilnEvent::Stop) => {
+                        if run.firing() {
+                            let _ = recorder.finish(CompletionReason::Stopped);
                         }
+                        run.stop();
+                        safety.reset();
+                        controller.reset();
 
-                        state = KilnState::Idle;
-                        runtime = 0;
-                        schedule = None;
+                        if let Err(error) = PendingStart::clear(&firings_folder) {
+                            error!("unable to clear waiting schedule: {}", error);
+                        }
+                    }
+                    Some(KilnEvent::Wait(pending)) => {
+                        let waiting = run.wait(pending.clone());
+
+                        if waiting {
+                            if let Err(error) = pending.save(&firings_folder) {
+                                error!("unable to save waiting schedule: {}", error);
+                            }
+                        }
+                    }
+                    Some(KilnEvent::Pause(mode)) if run.pause(mode) => {
+                        log_event(&mut recorder, run.runtime, EventKind::Paused { mode });
+                    }
+                    Some(KilnEvent::Resume) if run.resume() => {
+                        log_event(&mut recorder, run.runtime, EventKind::Resumed);
+                    }
+                    Some(KilnEvent::Skip(step)) => {
+                        if let Some((from, to)) = run.skip(step) {
+                            log_event(&mut recorder, run.runtime, EventKind::Skipped { from, to });
+                        }
+                    }
+                    Some(KilnEvent::Edit(steps)) => {
+                        if let Some(from) = run.edit(&steps) {
+                            if let Some(schedule) = &run.schedule {
+                                if let Err(error) = recorder.amend(schedule) {
+                                    error!("unable to record edited schedule: {}", error);
+                                }
+                            }
+                            log_event(
+                                &mut recorder,
+                                run.runtime,
+                                EventKind::Edited { from, steps },
+                            );
+                        }
+                    }
+                    Some(KilnEvent::Hold {
+                        temperature,
+                        timeout,
+                    }) => {
+                        if !temperature.is_finite() || temperature > config.max_temp {
+                            error!(
+                                temperature,
+                                "refusing to hold a set point above the maximum temperature"
+                            );
+                        } else if run.hold(temperature, timeout) {
+                            controller.reset();
+                            if let Err(error) = PendingStart::clear(&firings_folder) {
+                                error!("unable to clear waiting schedule: {}", error);
+                            }
+                        }
+                    }
+                    Some(KilnEvent::Autotune(target)) => {
+                        if !target.is_finite() || target > config.max_temp {
+                            error!(target, "refusing to autotune above the maximum temperature");
+                        } else {
+                            run.tune(target);
+                        }
+                    }
+                    Some(KilnEvent::ApplyGains) => match proposed {
+                        Some(_) if !config.gain_schedule.is_empty() => error!(
+                            "refusing to apply autotuned gains over the gain schedule, \
+                             remove it from the config first"
+                        ),
+                        Some(gains) => {
+                            info!(?gains, message = "applying autotuned gains");
+                            if config.controller != ControlStrategy::Pid {
+                                warn!("autotuned gains only apply to the pid controller");
+                            }
+                            proposed = None;
+                            controller.set_gains(&gains);
+                            let _ = update_tx.send(Command::SaveGains { gains });
+                        }
+                        None => warn!("attempting to apply gains without autotuning first"),
+                    },
+                    Some(KilnEvent::Complete) => {
+                        let _ = recorder.finish(CompletionReason::Complete);
+                        run.stop();
+                    }
+                    Some(KilnEvent::Shutdown(done)) => {
+                        // The firing is left unfinished, so it's resumed on the next start.
+                        info!("shutting down kiln");
+                        driver.stop().await;
+                        if let Some(watchdog) = watchdog.take() {
+                            if let Err(error) = watchdog.disarm() {
+                                error!("unable to disarm watchdog: {}", error);
+                            }
+                        }
+                        let _ = done.send(());
+                        break;
                     }
                     _ => (),
                 };
 
-                match state {
-                    KilnState::Running => {
-                        set_point = schedule.clone().expect("valid").target_temperature(runtime);
-                        let error = set_point - temperature;
-                        let p = pid.compute(&set_point, temperature);
-                        let f = Fuzzy::init(config.fuzzy_step_size).compute(error as f32); //ugh
-                        let on_time = (interval as f64 * p).floor() as u64;
-                        let off_time = (interval as u64) - on_time;
-
-                        info!(on_time, off_time, "p: {} f: {}", p as f64, f as f64);
-                        heater.on();
-                        sleep(Duration::from_millis(on_time)).await;
-                        heater.off();
-                        sleep(Duration::from_millis(off_time)).await;
-
-                        runtime += interval / 1000;
-
-                        if runtime > schedule.clone().expect("valid").total_duration() {
-                            info!("schedule complete, stopping kiln");
-                            update_queue
-                                .lock()
-                                .expect("unable to lock update queue")
-                                .push_back(KilnEvent::Complete);
+                let fresh = matches!(reading, Ok(Some(_)));
+                let fault = match reading {
+                    Ok(Some(reading)) => {
+                        temperature = reading;
+                        safety.check(temperature, run.target(temperature), last_on_time, interval)
+                    }
+                    Ok(None) => None,
+                    Err(fault) => Some(fault),
+                }
+                .or_else(|| run.overdue());
+
+                if let Some(fault) = fault {
+                    if run.state != KilnState::Fault {
+                        driver.set(0.0);
+                        if run.firing() {
+                            let _ = recorder.finish(CompletionReason::Fault);
                         }
+                        if run.state == KilnState::Waiting {
+                            let _ = PendingStart::clear(&firings_folder);
+                        }
+                        run.trip(fault);
                     }
-                    KilnState::Idle => {
-                        sleep(Duration::from_millis((interval) as u64)).await;
+                }
+
+                let poll = control(
+                    &mut run,
+                    controller.as_mut(),
+                    &config,
+                    if fresh { Some(temperature) } else { None },
+                    interval,
+                    None,
+                );
+
+                if poll.tuned.is_some() {
+                    proposed = poll.tuned;
+                }
+
+                if warning.is_none() {
+                    if let Some(warning) = poll.warning {
+                        warn!(?warning, message = "cannot cool fast enough");
                     }
-                };
+                }
+                warning = poll.warning;
+
+                driver.set(poll.duty);
+                sleep_unless_shutdown(Duration::from_millis(interval as u64), &mut shutdown).await;
+
+                let heater_on_time = driver.on_time();
+                last_on_time = heater_on_time;
+
+                if poll.driving && run.advance(interval) {
+                    info!("run complete, stopping kiln");
+                    update_queue
+                        .lock()
+                        .expect("unable to lock update queue")
+                        .push_back(KilnEvent::Complete);
+                }
+
+                if run.firing() && fresh {
+                    let sample = Sample {
+                        timestamp: Utc::now(),
+                        runtime: run.runtime,
+                        temperature,
+                        set_point: poll.set_point,
+                        output: poll.output,
+                        on_time: heater_on_time,
+                        duty_cycle: heater_on_time as f64 / interval as f64,
+                    };
+
+                    if let Err(error) = recorder.record(&sample) {
+                        error!("unable to record sample: {}", error);
+                    }
+
+                    if let Err(error) = recorder.checkpoint(run.runtime) {
+                        error!("unable to checkpoint firing: {}", error);
+                    }
+                }
 
+                let cold_junction = match thermocouple.read_internal() {
+                    Ok(temperature) => Some(temperature),
+                    Err(error) => {
+                        trace!("unable to read cold junction: {}", error);
+                        None
+                    }
+                };
+                let step = run.step();
                 let update = KilnUpdate {
-                    runtime,
-                    state,
-                    set_point,
-                    temperature: *temperature,
+                    runtime: run.runtime,
+                    state: run.state,
+                    set_point: poll.set_point,
+                    temperature,
+                    fault: run.fault.clone(),
+                    countdown: run.countdown(Utc::now()),
+                    gains: proposed,
+                    gain_band: controller.gain_band(),
+                    cold_junction,
+                    terms: controller.terms(),
+                    duty_cycle: poll.duty,
+                    step: step.map(|(index, _)| index),
+                    step_kind: step.map(|(_, kind)| kind),
+                    remaining: run.remaining(),
+                    schedule: run.schedule.as_ref().map(|schedule| schedule.name.clone()),
+                    warning,
                 };
                 let update = serde_json::to_string(&update)
                     .expect("expected valid kiln update serialization");
@@ -167,6 +1017,12 @@ impl Kiln {
                     channel: channel.to_string(),
                     data: update,
                 });
+
+                if let Some(watchdog) = watchdog.as_mut() {
+                    if let Err(error) = watchdog.pet() {
+                        error!("unable to pet watchdog: {}", error);
+                    }
+                }
             }
         });
 
@@ -175,14 +1031,59 @@ impl Kiln {
             while let Some(event) = rx.recv().await {
                 trace!("kiln got event");
                 match event {
-                    KilnEvent::Start(schedule) => handler_queue
+                    KilnEvent::Start(schedule, from) => handler_queue
                         .lock()
                         .expect("unable to lock")
-                        .push_back(KilnEvent::Start(schedule)),
+                        .push_back(KilnEvent::Start(schedule, from)),
                     KilnEvent::Stop => handler_queue
                         .lock()
                         .expect("unable to lock")
                         .push_back(KilnEvent::Stop),
+                    KilnEvent::Pause(mode) => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::Pause(mode)),
+                    KilnEvent::Resume => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::Resume),
+                    KilnEvent::Skip(step) => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::Skip(step)),
+                    KilnEvent::Edit(steps) => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::Edit(steps)),
+                    KilnEvent::Wait(pending) => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::Wait(pending)),
+                    KilnEvent::Hold {
+                        temperature,
+                        timeout,
+                    } => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::Hold {
+                            temperature,
+                            timeout,
+                        }),
+                    KilnEvent::Autotune(target) => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::Autotune(target)),
+                    KilnEvent::ApplyGains => handler_queue
+                        .lock()
+                        .expect("unable to lock")
+                        .push_back(KilnEvent::ApplyGains),
+                    KilnEvent::Shutdown(done) => {
+                        handler_queue
+                            .lock()
+                            .expect("unable to lock")
+                            .push_back(KilnEvent::Shutdown(done));
+                        let _ = shutdown_tx.send(true);
+                    }
                     _ => (),
                 }
             }
@@ -205,3 +1106,850 @@ impl std::fmt::Display for KilnError {
         write!(f, "Kiln Error")
     }
 }
+
+#[cfg(test)]
+mod kiln_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::tempdir;
+    use tokio::time::timeout;
+
+    use crate::config::DEFAULT_MAX_TEMP;
+    use crate::schedule::{Schedule, TemperatureScale};
+    use crate::sensor::thermocouple::ThermocoupleError;
+
+    struct MockSensor(f64);
+
+    impl TemperatureSensor for MockSensor {
+        fn read(&mut self) -> Result<f64, ThermocoupleError> {
+            Ok(self.0)
+        }
+
+        fn read_internal(&mut self) -> Result<f64, ThermocoupleError> {
+            Ok(self.0)
+        }
+    }
+
+    struct BrokenSensor;
+
+    impl TemperatureSensor for BrokenSensor {
+        fn read(&mut self) -> Result<f64, ThermocoupleError> {
+            Err(ThermocoupleError::OpenCircuit)
+        }
+
+        fn read_internal(&mut self) -> Result<f64, ThermocoupleError> {
+            Ok(20.0)
+        }
+    }
+
+    struct MockHeater(Arc<AtomicUsize>);
+
+    impl HeaterOutput for MockHeater {
+        fn on(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn off(&mut self) {}
+
+        fn toggle(&mut self) {}
+    }
+
+    fn config() -> KilnConfig {
+        KilnConfig::default()
+    }
+
+    fn schedule() -> NormalizedSchedule {
+        Schedule {
+            name: "test".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "ambient to 100 over 1 hour".to_string(),
+                "hold for 1 hour".to_string(),
+            ],
+        }
+        .normalize()
+        .unwrap()
+    }
+
+    /// Waits for the kiln to report the given state, returning the update.
+    async fn reported(
+        updates: &mut broadcast::Receiver<Command>,
+        state: KilnState,
+    ) -> Option<String> {
+        let state = format!(r#""state":"{:?}""#, state);
+        let update = timeout(Duration::from_secs(5), async {
+            while let Ok(command) = updates.recv().await {
+                if let Command::Update { data, .. } = command {
+                    if data.contains(&state) {
+                        return Some(data);
+                    }
+                }
+            }
+            None
+        });
+
+        update.await.ok().flatten()
+    }
+
+    #[test]
+    fn should_freeze_the_clock_while_paused() {
+        let mut run = RunState::default();
+        run.start(schedule());
+        run.advance(5000);
+
+        assert!(run.pause(PauseMode::Off));
+        assert!(!run.heating());
+        assert!(!run.start(schedule()), "started over a paused schedule");
+
+        run.advance(5000);
+        assert_eq!(run.runtime, 5);
+
+        assert!(run.resume());
+        assert!(!run.resume());
+        run.advance(5000);
+        assert_eq!(run.runtime, 10);
+    }
+
+    #[test]
+    fn should_hold_the_set_point_while_paused() {
+        let mut run = RunState::default();
+        run.start(schedule());
+        run.advance(1_800_000);
+
+        run.pause(PauseMode::Hold);
+        assert!(run.heating());
+        assert_eq!(run.set_point(), 62.5);
+    }
+
+    #[test]
+    fn should_hold_a_manual_set_point() {
+        let mut run = RunState::default();
+
+        assert!(run.hold(90.0, Some(10)));
+        assert!(run.heating());
+        assert!(!run.firing());
+        assert_eq!(run.set_point(), 90.0);
+
+        assert!(!run.advance(9000));
+        assert!(run.hold(120.0, Some(10)), "couldn't change the set point");
+        assert_eq!((run.runtime, run.set_point()), (0, 120.0));
+        assert!(!run.advance(9000));
+        assert!(run.advance(1000), "didn't time out");
+
+        run.stop();
+        assert_eq!(run.set_point(), 0.0);
+        assert!(run.hold(90.0, None));
+        assert!(!run.advance(u32::MAX / 2));
+    }
+
+    #[test]
+    fn should_only_hold_to_a_manual_set_point_once_reached() {
+        let mut run = RunState::default();
+        let mut safety = Safety::new(&config());
+        run.hold(600.0, None);
+
+        // Climbing from cold at 10C a minute takes the best part of an hour.
+        let mut temperature = 20.0;
+        while temperature < 600.0 {
+            let target = run.target(temperature);
+            assert_eq!(target, None);
+            assert_eq!(safety.check(temperature, target, 1000, 1000), None);
+            temperature += 1.0 / 6.0;
+        }
+        assert_eq!(run.target(temperature), Some(600.0));
+
+        // Falling behind after getting there still trips.
+        let fault = (0..=config().max_difference_time)
+            .find_map(|_| safety.check(500.0, run.target(500.0), 1000, 1000));
+        assert!(matches!(fault, Some(Fault::Deviation { .. })));
+
+        // A new set point has to be reached all over again.
+        run.hold(700.0, None);
+        assert_eq!(run.target(600.0), None);
+    }
+
+    #[test]
+    fn should_not_hold_while_firing() {
+        let mut run = RunState::default();
+        run.hold(90.0, None);
+
+        // A schedule takes over from manual mode, but not the other way around.
+        assert!(run.start(schedule()));
+        assert_eq!(run.set_point(), schedule().target_temperature(0));
+        assert!(!run.hold(90.0, None));
+        assert_eq!(run.state, KilnState::Running);
+    }
+
+    #[test]
+    fn should_autotune_from_idle() {
+        let mut run = RunState::default();
+
+        assert!(run.tune(500.0));
+        assert!(!run.heating());
+        assert_eq!(run.set_point(), 500.0);
+        assert_eq!(run.relay(20.0, 1000), 1.0);
+        assert_eq!(run.relay(510.0, 1000), 0.0);
+        assert!(run.tuned().is_none());
+
+        // Firing takes over from tuning, but not the other way around.
+        assert!(run.start(schedule()));
+        assert!(!run.tune(500.0));
+        assert_eq!(run.relay(20.0, 1000), 0.0);
+        assert_eq!(run.state, KilnState::Running);
+    }
+
+    #[test]
+    fn should_hold_autotuning_to_its_target_and_time_limit() {
+        let mut run = RunState::default();
+
+        run.tune(500.0);
+        assert_eq!(run.target(500.0), Some(500.0));
+        assert!(run.overdue().is_none());
+
+        // Never climbs through the target.
+        run.relay(20.0, TUNING_LIMIT * 1000 + 1000);
+        assert_eq!(
+            run.overdue(),
+            Some(Fault::TuningTimeout {
+                target: 500.0,
+                seconds: TUNING_LIMIT
+            })
+        );
+
+        run.trip(run.overdue().unwrap());
+        assert_eq!(run.target(20.0), None);
+    }
+
+    #[test]
+    fn should_autotune_from_cold_without_falling_behind() {
+        let mut run = RunState::default();
+        let mut safety = Safety::new(&config());
+        run.tune(500.0);
+
+        // Flat out from cold at 8C a minute, it's an hour before the relay first switches off.
+        let mut temperature = 20.0;
+        let mut polls = 0;
+        while temperature < 500.0 {
+            let duty = run.relay(temperature, 1000);
+            assert_eq!(duty, 1.0);
+
+            let target = run.target(temperature);
+            assert_eq!(safety.check(temperature, target, 1000, 1000), None);
+            temperature += 8.0 / 60.0;
+            polls += 1;
+        }
+
+        assert!(polls > config().max_difference_time);
+        assert_eq!(run.target(temperature), Some(500.0));
+    }
+
+    #[test]
+    fn should_wait_to_start() {
+        let mut run = RunState::default();
+        let now = Utc::now();
+        let pending = PendingStart {
+            schedule: schedule(),
+            from: StartFrom::Beginning,
+            at: now + chrono::Duration::hours(1),
+        };
+
+        assert!(run.wait(pending.clone()));
+        assert!(!run.heating());
+        assert_eq!(run.countdown(now), Some(3600));
+        assert!(run.due(now).is_none());
+
+        let due = run.due(now + chrono::Duration::hours(2));
+        assert!(due.is_some());
+        assert_eq!(run.state, KilnState::Idle);
+        assert!(run.start(due.unwrap().schedule));
+
+        // Can't wait over a running schedule.
+        assert!(!run.wait(pending.clone()));
+
+        run.stop();
+        run.wait(pending);
+        run.stop();
+        assert_eq!(run.countdown(now), None);
+    }
+
+    #[test]
+    fn should_skip_to_the_next_step() {
+        let mut run = RunState::default();
+        assert_eq!(run.skip(None), None);
+
+        run.start(schedule());
+        run.advance(600_500);
+
+        assert_eq!(run.skip(None), Some((0, 1)));
+        assert_eq!((run.runtime, run.remainder), (3600, 0));
+        assert_eq!(run.set_point(), 100.0);
+
+        // Past the last step, the schedule completes on the next advance.
+        assert_eq!(run.skip(None), Some((1, 2)));
+        assert!(run.advance(1000));
+    }
+
+    #[test]
+    fn should_report_the_step_and_time_remaining() {
+        let mut run = RunState::default();
+        assert_eq!((run.step(), run.remaining()), (None, None));
+
+        run.start(schedule());
+        run.advance(600_000);
+        assert_eq!(run.step(), Some((0, StepKind::Heat)));
+        assert_eq!(run.remaining(), Some(6600));
+
+        run.skip(None);
+        assert_eq!(run.step(), Some((1, StepKind::Hold)));
+        assert_eq!(run.remaining(), Some(3600));
+
+        run.stop();
+        run.hold(90.0, Some(60));
+        run.advance(20_000);
+        assert_eq!((run.step(), run.remaining()), (None, Some(40)));
+    }
+
+    #[test]
+    fn should_warn_when_unable_to_cool_fast_enough() {
+        let mut run = RunState::default();
+        let cooling = Schedule {
+            name: "slow cool".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "ambient to 100 over 1 hour".to_string(),
+                "100 to 50 over 1 hour".to_string(),
+            ],
+        }
+        .normalize()
+        .unwrap();
+        run.start(cooling);
+        assert!(run.warning(100.0, 0.0).is_none(), "warned while heating");
+
+        run.advance(5_400_000);
+        assert_eq!(run.set_point(), 75.0);
+        assert!(run.warning(80.0, 0.0).is_none());
+        // Still heating, so it's down to the controller rather than the kiln.
+        assert!(run.warning(90.0, 0.2).is_none());
+        assert_eq!(
+            run.warning(90.0, 0.0),
+            Some(Warning::CannotCoolFastEnough { difference: 15.0 })
+        );
+
+        // Not while the clock's stopped.
+        run.pause(PauseMode::Hold);
+        assert!(run.warning(90.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn should_skip_to_a_given_step() {
+        let mut run = RunState::default();
+        run.start(schedule());
+        run.pause(PauseMode::Hold);
+
+        assert_eq!(run.skip(Some(1)), Some((0, 1)));
+        assert_eq!(run.runtime, 3600);
+        assert_eq!(run.skip(Some(0)), Some((1, 0)));
+        assert_eq!(run.runtime, 0);
+
+        assert_eq!(run.skip(Some(2)), None);
+        assert_eq!(run.runtime, 0);
+    }
+
+    #[test]
+    fn should_edit_the_remaining_steps() {
+        let mut run = RunState::default();
+        let steps = vec!["hold for 2 hours".to_string()];
+        assert_eq!(run.edit(&steps), None);
+
+        run.start(schedule());
+        run.advance(5_400_000);
+
+        // Cuts the hold short at 5400s, then holds for 2 hours from there.
+        assert_eq!(run.edit(&steps), Some(2));
+        assert_eq!(run.runtime, 5400);
+        assert!(!run.advance(5_400_000), "completed the original hold");
+
+        assert_eq!(run.edit(&["hold until done".to_string()]), None);
+        assert_eq!(
+            run.schedule.as_ref().unwrap().total_duration(),
+            5400 + 2 * 3600
+        );
+    }
+
+    #[test]
+    fn should_only_ramp_while_running() {
+        let mut run = RunState::default();
+        let climb = schedule().ramp_rate(0);
+
+        assert!(climb > 0.0);
+        assert_eq!(run.ramp_rate(), 0.0);
+
+        run.start(schedule());
+        assert_eq!(run.ramp_rate(), climb);
+
+        run.pause(PauseMode::Hold);
+        assert_eq!(run.ramp_rate(), 0.0, "paused clock still climbing");
+
+        run.resume();
+        run.advance(3600 * 1000);
+        assert_eq!(run.ramp_rate(), 0.0, "still climbing while holding");
+    }
+
+    #[test]
+    fn should_fast_forward_to_the_measured_temperature() {
+        let mut run = RunState::default();
+        run.start(schedule());
+
+        run.fast_forward(62.5);
+        assert_eq!(run.runtime, 1800);
+
+        // Nowhere in the schedule is this hot, so it starts at the beginning.
+        run.stop();
+        run.start(schedule());
+        run.fast_forward(500.0);
+        assert_eq!(run.runtime, 0);
+    }
+
+    #[test]
+    fn should_resume_from_checkpoint_after_short_interruption() {
+        let checkpoint = Checkpoint {
+            runtime: 1800,
+            timestamp: Utc::now() - chrono::Duration::seconds(60),
+        };
+
+        assert_eq!(resume_point(&schedule(), &checkpoint, 30.0, 600), 1800);
+    }
+
+    #[test]
+    fn should_resume_from_temperature_after_long_interruption() {
+        let schedule = schedule();
+        let checkpoint = Checkpoint {
+            runtime: 3000,
+            timestamp: Utc::now() - chrono::Duration::hours(2),
+        };
+
+        // Cooled to 62.5C, halfway up the first ramp.
+        assert_eq!(resume_point(&schedule, &checkpoint, 62.5, 600), 1800);
+        // Colder than the start of the schedule.
+        assert_eq!(resume_point(&schedule, &checkpoint, 15.0, 600), 0);
+        // Still at temperature.
+        assert_eq!(resume_point(&schedule, &checkpoint, 100.0, 600), 3000);
+    }
+
+    #[test]
+    fn should_not_fire_again_when_resuming_past_the_peak() {
+        let schedule = Schedule {
+            name: "test".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "ambient to 100 over 1 hour".to_string(),
+                "hold for 1 hour".to_string(),
+                "100 to 40 over 1 hour".to_string(),
+            ],
+        }
+        .normalize()
+        .unwrap();
+        let interrupted = |runtime| Checkpoint {
+            runtime,
+            timestamp: Utc::now() - chrono::Duration::hours(2),
+        };
+
+        // Holding at the peak carries on with the hold.
+        assert_eq!(resume_point(&schedule, &interrupted(5400), 62.5, 600), 5400);
+        // Cooling picks up where the step passes through 55C.
+        assert_eq!(resume_point(&schedule, &interrupted(9000), 55.0, 600), 9900);
+        // Colder than the end of the step, it carries on from the checkpoint.
+        assert_eq!(resume_point(&schedule, &interrupted(9000), 30.0, 600), 9000);
+    }
+
+    #[tokio::test]
+    async fn should_heat_a_cold_kiln_when_running() -> Result<()> {
+        let dir = tempdir()?;
+        let (manager, mut updates) = broadcast::channel(32);
+        let heater_on = Arc::new(AtomicUsize::new(0));
+        let schedule = schedule();
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(heater_on.clone())),
+            1000,
+            manager,
+            config(),
+            dir.path().to_str().unwrap().to_string(),
+            None,
+        )
+        .await?;
+        kiln.send(KilnEvent::Start(schedule, StartFrom::Beginning))
+            .await?;
+
+        let running = reported(&mut updates, KilnState::Running).await;
+
+        assert!(running.is_some(), "kiln never reported running");
+        assert!(heater_on.load(Ordering::SeqCst) > 0);
+
+        let running = running.unwrap();
+        assert!(running.contains(r#""schedule":"test""#));
+        assert!(running.contains(r#""step":0,"stepKind":"Heat""#));
+        assert!(running.contains(r#""coldJunction":20.0"#));
+        assert!(running.contains(r#""terms":{"proportional":"#));
+        assert_eq!(Firing::all(dir.path().to_str().unwrap())?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_heat_to_a_manual_set_point() -> Result<()> {
+        let dir = tempdir()?;
+        let (manager, mut updates) = broadcast::channel(32);
+        let heater_on = Arc::new(AtomicUsize::new(0));
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(heater_on.clone())),
+            1000,
+            manager,
+            config(),
+            dir.path().to_str().unwrap().to_string(),
+            None,
+        )
+        .await?;
+        kiln.send(KilnEvent::Hold {
+            temperature: 90.0,
+            timeout: Some(1),
+        })
+        .await?;
+
+        let manual = reported(&mut updates, KilnState::Manual).await;
+        assert!(manual.unwrap().contains(r#""setPoint":90.0"#));
+        assert!(heater_on.load(Ordering::SeqCst) > 0);
+
+        // Goes idle after the timeout, without recording a firing.
+        assert!(reported(&mut updates, KilnState::Idle).await.is_some());
+        assert!(Firing::all(dir.path().to_str().unwrap())?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_start_a_warm_kiln_from_its_temperature() -> Result<()> {
+        let dir = tempdir()?;
+        let (manager, mut updates) = broadcast::channel(32);
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(62.5)),
+            Box::new(MockHeater(Arc::new(AtomicUsize::new(0)))),
+            1000,
+            manager,
+            config(),
+            dir.path().to_str().unwrap().to_string(),
+            None,
+        )
+        .await?;
+        kiln.send(KilnEvent::Start(schedule(), StartFrom::Temperature))
+            .await?;
+
+        let running = reported(&mut updates, KilnState::Running).await.unwrap();
+        let running: serde_json::Value = serde_json::from_str(&running)?;
+        // Half way up the first ramp, and a poll on.
+        assert_eq!(running["runtime"], 1801);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_refuse_a_schedule_hotter_than_the_kiln() -> Result<()> {
+        let dir = tempdir()?;
+        let (manager, mut updates) = broadcast::channel(32);
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(Arc::new(AtomicUsize::new(0)))),
+            1000,
+            manager,
+            KilnConfig {
+                max_temp: 90.0,
+                ..config()
+            },
+            dir.path().to_str().unwrap().to_string(),
+            None,
+        )
+        .await?;
+        kiln.send(KilnEvent::Start(schedule(), StartFrom::Beginning))
+            .await?;
+
+        // Holding would be refused with a schedule running.
+        kiln.send(KilnEvent::Hold {
+            temperature: 50.0,
+            timeout: None,
+        })
+        .await?;
+        assert!(reported(&mut updates, KilnState::Manual).await.is_some());
+        assert!(Firing::all(dir.path().to_str().unwrap())?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_a_start_that_survives_restarts() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap().to_string();
+        let (manager, mut updates) = broadcast::channel(32);
+        let pending = PendingStart {
+            schedule: schedule(),
+            from: StartFrom::Beginning,
+            at: Utc::now() + chrono::Duration::seconds(2),
+        };
+        pending.save(&directory)?;
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(Arc::new(AtomicUsize::new(0)))),
+            1000,
+            manager,
+            config(),
+            directory.clone(),
+            None,
+        )
+        .await?;
+
+        let waiting = reported(&mut updates, KilnState::Waiting).await;
+        assert!(!waiting.unwrap().contains(r#""countdown":null"#));
+
+        assert!(reported(&mut updates, KilnState::Running).await.is_some());
+        assert!(PendingStart::load(&directory)?.is_none());
+        assert_eq!(Firing::all(&directory)?.len(), 1);
+
+        kiln.send(KilnEvent::Stop).await?;
+        let mut pending = pending;
+        pending.at = Utc::now() + chrono::Duration::hours(1);
+        kiln.send(KilnEvent::Wait(pending)).await?;
+        assert!(reported(&mut updates, KilnState::Waiting).await.is_some());
+        assert!(PendingStart::load(&directory)?.is_some());
+
+        // Cancelled by stopping.
+        kiln.send(KilnEvent::Stop).await?;
+        assert!(reported(&mut updates, KilnState::Idle).await.is_some());
+        assert!(PendingStart::load(&directory)?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_not_wait_once_started_by_hand() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap().to_string();
+        let (manager, mut updates) = broadcast::channel(32);
+        let pending = PendingStart {
+            schedule: schedule(),
+            from: StartFrom::Beginning,
+            at: Utc::now() + chrono::Duration::hours(1),
+        };
+        pending.save(&directory)?;
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(Arc::new(AtomicUsize::new(0)))),
+            100,
+            manager.clone(),
+            config(),
+            directory.clone(),
+            None,
+        )
+        .await?;
+        assert!(reported(&mut updates, KilnState::Waiting).await.is_some());
+
+        let short = Schedule {
+            name: "short".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "ambient to 30 over 1 second".to_string(),
+                "hold for 1 second".to_string(),
+            ],
+        }
+        .normalize()
+        .unwrap();
+        kiln.send(KilnEvent::Start(short, StartFrom::Beginning))
+            .await?;
+        assert!(reported(&mut updates, KilnState::Running).await.is_some());
+        assert!(PendingStart::load(&directory)?.is_none());
+        // Runs to completion.
+        assert!(reported(&mut updates, KilnState::Idle).await.is_some());
+
+        let (done, stopped) = oneshot::channel();
+        kiln.send(KilnEvent::Shutdown(done)).await?;
+        timeout(Duration::from_secs(5), stopped).await??;
+
+        // Restarted, the schedule that was waiting doesn't start on its own.
+        let mut updates = manager.subscribe();
+        let _kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(Arc::new(AtomicUsize::new(0)))),
+            100,
+            manager,
+            config(),
+            directory.clone(),
+            None,
+        )
+        .await?;
+        for _ in 0..5 {
+            assert!(reported(&mut updates, KilnState::Idle).await.is_some());
+        }
+        assert!(PendingStart::load(&directory)?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_resume_an_interrupted_firing() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap().to_string();
+        let (manager, mut updates) = broadcast::channel(32);
+        let heater_on = Arc::new(AtomicUsize::new(0));
+
+        let id = Recorder::new(&directory).start(&schedule())?;
+
+        let _kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(heater_on.clone())),
+            1000,
+            manager,
+            config(),
+            directory.clone(),
+            None,
+        )
+        .await?;
+
+        let running = reported(&mut updates, KilnState::Running).await;
+
+        assert!(running.is_some(), "kiln never resumed");
+        assert_eq!(Firing::all(&directory)?.len(), 1);
+        assert_eq!(Firing::unfinished(&directory)?.unwrap().0.id, id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_fault_when_over_temperature() -> Result<()> {
+        let dir = tempdir()?;
+        let (manager, mut updates) = broadcast::channel(32);
+        let heater_on = Arc::new(AtomicUsize::new(0));
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(DEFAULT_MAX_TEMP + 1.0)),
+            Box::new(MockHeater(heater_on.clone())),
+            100,
+            manager,
+            config(),
+            dir.path().to_str().unwrap().to_string(),
+            None,
+        )
+        .await?;
+
+        let fault = reported(&mut updates, KilnState::Fault).await;
+        assert!(fault.unwrap().contains(r#""type":"overTemperature""#));
+
+        // Refuses to start until the fault is cleared.
+        kiln.send(KilnEvent::Start(schedule(), StartFrom::Beginning))
+            .await?;
+        sleep(Duration::from_millis(300)).await;
+
+        assert_eq!(heater_on.load(Ordering::SeqCst), 0);
+        assert!(Firing::all(dir.path().to_str().unwrap())?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_fault_when_the_thermocouple_fails() -> Result<()> {
+        let dir = tempdir()?;
+        let (manager, mut updates) = broadcast::channel(32);
+        let heater_on = Arc::new(AtomicUsize::new(0));
+
+        let kiln = Kiln::start(
+            Box::new(BrokenSensor),
+            Box::new(MockHeater(heater_on.clone())),
+            100,
+            manager,
+            config(),
+            dir.path().to_str().unwrap().to_string(),
+            None,
+        )
+        .await?;
+        kiln.send(KilnEvent::Start(schedule(), StartFrom::Beginning))
+            .await?;
+
+        let fault = reported(&mut updates, KilnState::Fault).await;
+        assert!(fault.unwrap().contains(r#""type":"thermocouple""#));
+        assert_eq!(heater_on.load(Ordering::SeqCst), 0);
+
+        let firings = Firing::all(dir.path().to_str().unwrap())?;
+        assert_eq!(firings[0].reason, Some(CompletionReason::Fault));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_let_go_of_the_heater_on_shutdown() -> Result<()> {
+        let dir = tempdir()?;
+        let (manager, _updates) = broadcast::channel(32);
+        let heater_on = Arc::new(AtomicUsize::new(0));
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(heater_on.clone())),
+            60_000,
+            manager,
+            config(),
+            dir.path().to_str().unwrap().to_string(),
+            None,
+        )
+        .await?;
+        kiln.send(KilnEvent::Start(schedule(), StartFrom::Beginning))
+            .await?;
+
+        let (done, stopped) = oneshot::channel();
+        kiln.send(KilnEvent::Shutdown(done)).await?;
+        timeout(Duration::from_secs(5), stopped).await??;
+        sleep(Duration::from_millis(100)).await;
+
+        // The control loop has woken up, ended, and dropped the heater.
+        assert_eq!(Arc::strong_count(&heater_on), 1);
+        // Still there to pick back up on the next start.
+        assert!(Firing::unfinished(dir.path().to_str().unwrap())?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_pet_the_watchdog_until_shutdown() -> Result<()> {
+        let dir = tempdir()?;
+        let device = dir.path().join("watchdog");
+        let (manager, _updates) = broadcast::channel(32);
+
+        let kiln = Kiln::start(
+            Box::new(MockSensor(20.0)),
+            Box::new(MockHeater(Arc::new(AtomicUsize::new(0)))),
+            100,
+            manager,
+            config(),
+            dir.path().to_str().unwrap().to_string(),
+            Some(Watchdog::open(&device)?),
+        )
+        .await?;
+        sleep(Duration::from_millis(350)).await;
+
+        let (done, stopped) = oneshot::channel();
+        kiln.send(KilnEvent::Shutdown(done)).await?;
+        timeout(Duration::from_secs(5), stopped).await??;
+
+        let pets = std::fs::read_to_string(&device)?;
+        assert!(pets.starts_with("111"), "watchdog was only pet {}", pets);
+        assert!(pets.ends_with('V'), "watchdog wasn't disarmed");
+
+        Ok(())
+    }
+}
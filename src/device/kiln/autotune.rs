@@ -0,0 +1,167 @@
+//! Relay autotuning, after Åström and Hägglund. The heater is switched fully on below the target
+//! and off above it, and the size and period of the oscillation that settles in give the PID
+//! gains, by the Ziegler–Nichols rules.
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// Degrees either side of the target the heater switches at, so noise doesn't chatter the relay.
+const HYSTERESIS: f64 = 2.0;
+
+/// Full oscillations measured before proposing gains.
+const CYCLES: usize = 3;
+
+/// Half the swing of the relay's output, from off to fully on.
+const RELAY_AMPLITUDE: f64 = 0.5;
+
+/// Longest stretch of time, in seconds, autotuning gets to settle into an oscillation.
+pub const TUNING_LIMIT: u32 = 48 * 3600;
+
+/// Gains for the PID, in the units of the kiln config.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Gains {
+    pub proportional: f64,
+    pub integral: f64,
+    pub derivative: f64,
+}
+
+/// Oscillation seen between two times the heater switched on, where
+/// period: in seconds
+/// amplitude: half the difference between the highest and lowest temperature, in C
+#[derive(Clone, Copy, Debug)]
+struct Cycle {
+    period: f64,
+    amplitude: f64,
+}
+
+#[derive(Debug)]
+pub struct Autotune {
+    target: f64,
+    /// Seconds since tuning started.
+    time: f64,
+    heating: bool,
+    /// When the heater last switched back on. The warm up before doesn't count as a cycle.
+    switched_on: Option<f64>,
+    /// Lowest temperature while the heater's been on, and highest while it's been off.
+    low: f64,
+    high: f64,
+    cycles: Vec<Cycle>,
+}
+
+impl Autotune {
+    pub fn new(target: f64) -> Autotune {
+        Autotune {
+            target,
+            time: 0.0,
+            heating: true,
+            switched_on: None,
+            low: f64::MAX,
+            high: f64::MIN,
+            cycles: Vec::new(),
+        }
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// Takes the temperature, `delta` seconds after the last one, and returns the heater's duty
+    /// cycle until the next.
+    pub fn step(&mut self, temperature: f64, delta: f64) -> f64 {
+        self.time += delta;
+
+        if self.heating {
+            self.low = self.low.min(temperature);
+
+            if temperature > self.target + HYSTERESIS {
+                self.heating = false;
+                self.high = temperature;
+            }
+        } else {
+            self.high = self.high.max(temperature);
+
+            if temperature < self.target - HYSTERESIS {
+                if let Some(switched_on) = self.switched_on {
+                    self.cycles.push(Cycle {
+                        period: self.time - switched_on,
+                        amplitude: (self.high - self.low) / 2.0,
+                    });
+                }
+
+                self.heating = true;
+                self.switched_on = Some(self.time);
+                self.low = temperature;
+            }
+        }
+
+        if self.heating {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether tuning has gone on for longer than TUNING_LIMIT without settling.
+    pub fn expired(&self) -> bool {
+        self.time > TUNING_LIMIT as f64
+    }
+
+    /// The gains for the oscillation measured, once there have been enough cycles.
+    pub fn gains(&self) -> Option<Gains> {
+        if self.cycles.len() < CYCLES {
+            return None;
+        }
+
+        let cycles = &self.cycles[self.cycles.len() - CYCLES..];
+        let period = cycles.iter().map(|cycle| cycle.period).sum::<f64>() / CYCLES as f64;
+        let amplitude = cycles.iter().map(|cycle| cycle.amplitude).sum::<f64>() / CYCLES as f64;
+        let ultimate_gain = 4.0 * RELAY_AMPLITUDE / (PI * amplitude);
+
+        Some(Gains {
+            proportional: 0.6 * ultimate_gain,
+            integral: 1.2 * ultimate_gain / period,
+            derivative: 0.075 * ultimate_gain * period,
+        })
+    }
+}
+
+#[cfg(test)]
+mod autotune_tests {
+    use super::*;
+
+    fn approx(a: f64, b: f64) -> bool {
+        ((a - b) / b).abs() < 0.02
+    }
+
+    #[test]
+    fn should_relay_around_the_target() {
+        let mut tune = Autotune::new(500.0);
+
+        assert_eq!(tune.step(20.0, 1.0), 1.0);
+        assert_eq!(tune.step(501.0, 1.0), 1.0);
+        assert_eq!(tune.step(503.0, 1.0), 0.0);
+        assert_eq!(tune.step(499.0, 1.0), 0.0);
+        assert_eq!(tune.step(497.0, 1.0), 1.0);
+        assert!(tune.gains().is_none());
+    }
+
+    #[test]
+    fn should_propose_gains_from_the_oscillation() {
+        let mut tune = Autotune::new(500.0);
+
+        // Warms up, then swings 10 degrees either side of the target every 10 minutes.
+        for second in 0..600 {
+            tune.step(20.0 + second as f64, 1.0);
+        }
+        for second in 0..(600 * 5) {
+            let phase = 2.0 * PI * second as f64 / 600.0;
+            tune.step(500.0 + 10.0 * phase.sin(), 1.0);
+        }
+
+        let gains = tune.gains().unwrap();
+        let ultimate_gain = 4.0 * RELAY_AMPLITUDE / (PI * 10.0);
+        assert!(approx(gains.proportional, 0.6 * ultimate_gain));
+        assert!(approx(gains.integral, 1.2 * ultimate_gain / 600.0));
+        assert!(approx(gains.derivative, 0.075 * ultimate_gain * 600.0));
+    }
+}
@@ -1,98 +1,109 @@
-use std::collections::HashMap;
-use std::time::SystemTime;
+//! Ways of working out how hard to drive the heater, from the set point and the temperature.
+use std::fmt::Debug;
+use std::time::Instant;
 
-use rsfuzzy::*;
-pub struct Fuzzy {
-    engine: rsfuzzy::Engine,
-}
+use serde::Serialize;
 
-impl Fuzzy {
-    /// Ripped off from
-    ///   https://github.com/auseckas/rsfuzzy
-    pub fn init(step_size: f32) -> Fuzzy {
-        let mut engine = rsfuzzy::Engine::new();
-        let high_bound = 2.0 * step_size;
-        let low_bound = -high_bound;
-
-        let error = fz_input_var![
-            ("down", "negative", vec![low_bound, 0.0]),
-            ("triangle", "low", vec![-step_size, 0.0, step_size]),
-            ("up", "positive", vec![0.0, high_bound])
-        ];
-        engine.add_input_var("error", error, low_bound as usize, high_bound as usize);
-
-        let output = fz_output_var![
-            ("down", "heat", vec![0.0, 50.0]),
-            ("triangle", "hold", vec![25.0, 0.0, 75.0]),
-            ("up", "cool", vec![50.0, 100.0])
-        ];
-        engine.add_output_var("output", output, 0, 1);
-
-        let rules = vec![
-            ("if error is negative then output is heat"),
-            ("if error is low then output is hold"),
-            ("if error is positive then output is cool")
-        ];
-
-        engine.add_rules(rules);
-        engine.add_defuzz("centroid");
-
-        Fuzzy { engine }
-    }
+use crate::config::{ControlStrategy, FeedForwardConfig, KilnConfig};
+use crate::device::Gains;
 
-    /// todo: add derivative input variable
-    pub fn compute(self, error: f32) -> f32 {
-        let inputs = fz_set_inputs![("error", error)];
+mod fuzzy;
+mod pid;
+pub use fuzzy::Fuzzy;
+pub use pid::PID;
 
-        self.engine.calculate(inputs)
-    }
-}
+/// Seconds over which derivatives are smoothed, so thermocouple noise doesn't reach the heater.
+const DERIVATIVE_FILTER: f64 = 10.0;
+
+/// Range of the output, as the share of each PWM window the heater is on.
+const OUTPUT_MIN: f64 = 0.0;
+const OUTPUT_MAX: f64 = 1.0;
 
-#[derive(Debug)]
-pub struct PID {
-    k_i: f64,
-    k_p: f64,
-    k_d: f64,
-    last_now: std::time::SystemTime,
-    i_term: f64,
-    last_error: f64,
+/// What each part of a PID contributed to its last output, before clamping.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Terms {
+    pub proportional: f64,
+    pub integral: f64,
+    pub derivative: f64,
 }
 
-///  A pretty blatant ripoff/rewrite of:
-///    https://github.com/jbruce12000/kiln-controller/blob/master/lib/oven.py#L322
-impl PID {
-    pub fn init(k_i: f64, k_p: f64, k_d: f64) -> PID {
-        PID {
-            k_i,
-            k_p,
-            k_d,
-            last_now: SystemTime::now(),
-            i_term: 0.0,
-            last_error: 0.0,
-        }
-    }
+pub trait Controller: Debug + Send {
+    /// The heater's duty cycle, between 0 and 1, for the time since the last computation. The
+    /// `feed_forward` duty cycle is added to what the controller works out for itself.
+    fn compute(&mut self, set_point: f64, is_point: f64, feed_forward: f64) -> f64;
 
-    pub fn compute(&mut self, set_point: &f64, is_point: &f64) -> f64 {
-        let now = SystemTime::now();
-        let delta: u64 = self.last_now.elapsed().unwrap().as_secs();
+    /// Computes the output as if `delta` seconds have passed since the last computation.
+    fn compute_with_delta(
+        &mut self,
+        set_point: f64,
+        is_point: f64,
+        feed_forward: f64,
+        delta: f64,
+    ) -> f64;
 
-        let error: f64 = *set_point - *is_point;
+    /// Forgets everything, for a new firing or set point.
+    fn reset(&mut self);
 
-        self.i_term += error * delta as f64 * self.k_i;
-        let mut sorted = vec![-1.0, self.i_term, 1.0];
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        self.i_term = sorted[1];
+    /// Forgets when it last ran, for when the heater's been left off for a while.
+    fn idle(&mut self);
 
-        let d_error = (error - self.last_error) / delta as f64;
+    /// Switches to autotuned gains, for controllers that have them.
+    fn set_gains(&mut self, _gains: &Gains) {}
 
-        let o: f64 = (self.k_p * error) + self.i_term + self.k_d * d_error;
-        let mut sorted = vec![-1.0, o, 1.0];
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    /// Temperature of the band in the gain schedule the controller is working in, if it has one.
+    fn gain_band(&self) -> Option<f64> {
+        None
+    }
 
-        let output = sorted[1];
+    /// The terms behind the last output, for controllers that have them, while heating.
+    fn terms(&self) -> Option<Terms> {
+        None
+    }
+}
+
+/// Creates the controller selected in the config.
+pub fn from_config(config: &KilnConfig) -> Box<dyn Controller> {
+    match config.controller {
+        ControlStrategy::Pid => Box::new(PID::new(config)),
+        ControlStrategy::Fuzzy => Box::new(Fuzzy::new(config)),
+    }
+}
+
+/// Duty cycle that would keep the kiln following a schedule climbing `ramp_rate` degrees per
+/// second, at `temperature`, leaving the controller only the difference to make up.
+pub fn feed_forward(config: &FeedForwardConfig, ramp_rate: f64, temperature: f64) -> f64 {
+    config.ramp * ramp_rate * 3600.0 + config.loss * (temperature - config.ambient).max(0.0)
+}
+
+/// Seconds since `last_now`, which moves on to now. No time at all the first time around.
+fn since(last_now: &mut Option<Instant>) -> f64 {
+    let now = Instant::now();
+    let delta = match last_now {
+        Some(last_now) => now.duration_since(*last_now).as_secs_f64(),
+        None => 0.0,
+    };
+
+    *last_now = Some(now);
+    delta
+}
 
-        self.last_error = error;
-        self.last_now = now;
-        output
+#[cfg(test)]
+mod controller_tests {
+    use super::*;
+
+    #[test]
+    fn should_feed_forward_the_ramp_and_heat_loss() {
+        let config = FeedForwardConfig {
+            ramp: 0.002,
+            loss: 0.0005,
+            ambient: 25.0,
+        };
+
+        assert_eq!(feed_forward(&config, 0.0, 25.0), 0.0);
+        assert_eq!(feed_forward(&config, 0.0, 10.0), 0.0);
+        assert!((feed_forward(&config, 100.0 / 3600.0, 1025.0) - 0.7).abs() < 1e-9);
+        assert!(feed_forward(&config, -100.0 / 3600.0, 25.0) < 0.0);
+        let off = FeedForwardConfig::default();
+        assert_eq!(feed_forward(&off, 0.1, 1000.0), 0.0);
     }
 }
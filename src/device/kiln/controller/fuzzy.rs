@@ -0,0 +1,187 @@
+use std::time::Instant;
+
+use super::{since, Controller, DERIVATIVE_FILTER, OUTPUT_MAX, OUTPUT_MIN};
+use crate::config::KilnConfig;
+
+/// Seconds the change in error is judged over. Changing by a whole step in that long is quick.
+const HORIZON: f64 = 600.0;
+
+/// Seconds for the output to go from off to fully on, when the rules push it as hard as they can.
+const SWING: f64 = 60.0;
+
+/// How quickly to move the output, by how far below the set point the kiln is (rows: above, at,
+///   below) and how quickly that's changing (columns: closing in, steady, falling behind).
+const RULES: [[f64; 3]; 3] = [[-1.0, -0.5, 0.0], [-0.5, 0.0, 0.5], [0.0, 0.5, 1.0]];
+
+/// A fuzzy PI controller. Rather than the output itself, the rules give how quickly it should
+///   move, from the error and its rate of change, so the kiln settles on the set point without
+///   a standing error.
+#[derive(Debug)]
+pub struct Fuzzy {
+    /// Error, in C, that counts as fully above or below the set point.
+    step_size: f64,
+    output: f64,
+    last_now: Option<Instant>,
+    last_error: Option<f64>,
+    /// Rate of change of the error, in C per second, smoothed.
+    change: f64,
+}
+
+impl Fuzzy {
+    pub fn new(config: &KilnConfig) -> Fuzzy {
+        Fuzzy {
+            step_size: config.fuzzy_step_size as f64,
+            output: 0.0,
+            last_now: None,
+            last_error: None,
+            change: 0.0,
+        }
+    }
+}
+
+impl Controller for Fuzzy {
+    fn compute(&mut self, set_point: f64, is_point: f64, feed_forward: f64) -> f64 {
+        let delta = since(&mut self.last_now);
+
+        self.compute_with_delta(set_point, is_point, feed_forward, delta)
+    }
+
+    fn compute_with_delta(
+        &mut self,
+        set_point: f64,
+        is_point: f64,
+        feed_forward: f64,
+        delta: f64,
+    ) -> f64 {
+        let error = set_point - is_point;
+
+        if delta > 0.0 {
+            if let Some(last_error) = self.last_error {
+                let change = (error - last_error) / delta;
+                self.change += (change - self.change) * delta / (DERIVATIVE_FILTER + delta);
+            }
+
+            let rate = infer(
+                error / self.step_size,
+                self.change * HORIZON / self.step_size,
+            );
+            // Only the part the feed forward leaves over is learned.
+            self.output = (self.output + rate * delta / SWING)
+                .clamp(OUTPUT_MIN - feed_forward, OUTPUT_MAX - feed_forward);
+        }
+
+        self.last_error = Some(error);
+        (self.output + feed_forward).clamp(OUTPUT_MIN, OUTPUT_MAX)
+    }
+
+    fn reset(&mut self) {
+        self.idle();
+        self.output = 0.0;
+    }
+
+    /// Keeps the output, so it picks up where it left off once the kiln heats again after a
+    ///   pause.
+    fn idle(&mut self) {
+        self.last_now = None;
+        self.last_error = None;
+        self.change = 0.0;
+    }
+}
+
+/// How much `x`, in steps, is negative, around zero and positive. Always adds up to 1.
+fn memberships(x: f64) -> [f64; 3] {
+    let x = x.clamp(-1.0, 1.0);
+
+    [(-x).max(0.0), 1.0 - x.abs(), x.max(0.0)]
+}
+
+/// Fires every rule as strongly as the weaker of its two inputs, and averages what they say.
+fn infer(error: f64, change: f64) -> f64 {
+    let errors = memberships(error);
+    let changes = memberships(change);
+    let mut total = 0.0;
+    let mut weights = 0.0;
+
+    for (row, error) in RULES.iter().zip(errors.iter()) {
+        for (rate, change) in row.iter().zip(changes.iter()) {
+            let weight = error.min(*change);
+            total += weight * rate;
+            weights += weight;
+        }
+    }
+
+    total / weights
+}
+
+#[cfg(test)]
+mod fuzzy_tests {
+    use super::*;
+
+    fn fuzzy() -> Fuzzy {
+        Fuzzy {
+            step_size: 10.0,
+            output: 0.0,
+            last_now: None,
+            last_error: None,
+            change: 0.0,
+        }
+    }
+
+    #[test]
+    fn should_infer_from_the_error_and_its_change() {
+        assert_eq!(infer(0.0, 0.0), 0.0);
+        assert_eq!(infer(5.0, 0.0), 0.5);
+        assert_eq!(infer(5.0, 5.0), 1.0);
+        assert_eq!(infer(-5.0, -5.0), -1.0);
+
+        // Far below the set point, but closing in quickly.
+        assert_eq!(infer(5.0, -5.0), 0.0);
+        assert!(infer(0.5, 0.0) > 0.0 && infer(0.5, 0.0) < 0.5);
+    }
+
+    #[test]
+    fn should_heat_harder_the_longer_the_kiln_is_below_the_set_point() {
+        let mut controller = fuzzy();
+
+        let first = controller.compute_with_delta(120.0, 100.0, 0.0, 1.0);
+        let second = controller.compute_with_delta(120.0, 100.0, 0.0, 1.0);
+        assert!(first > 0.0);
+        assert!(second > first);
+
+        for _ in 0..600 {
+            controller.compute_with_delta(120.0, 100.0, 0.0, 1.0);
+        }
+        assert_eq!(controller.compute_with_delta(120.0, 100.0, 0.0, 1.0), 1.0);
+
+        // Eases off once above it.
+        assert!(controller.compute_with_delta(120.0, 140.0, 0.0, 1.0) < 1.0);
+    }
+
+    #[test]
+    fn should_hold_its_output_at_the_set_point() {
+        let mut controller = fuzzy();
+        controller.output = 0.4;
+
+        for _ in 0..10 {
+            assert_eq!(controller.compute_with_delta(120.0, 120.0, 0.0, 1.0), 0.4);
+        }
+    }
+
+    #[test]
+    fn should_reset_between_firings() {
+        let mut controller = fuzzy();
+
+        for _ in 0..10 {
+            controller.compute_with_delta(120.0, 100.0, 0.0, 1.0);
+        }
+
+        // Picks up where it left off after a pause...
+        let output = controller.output;
+        controller.idle();
+        assert_eq!(controller.compute(120.0, 100.0, 0.0), output);
+
+        // ...but starts from scratch for a new firing.
+        controller.reset();
+        assert_eq!(controller.compute(120.0, 100.0, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,306 @@
+use std::time::Instant;
+
+use super::{since, Controller, Terms, DERIVATIVE_FILTER, OUTPUT_MAX, OUTPUT_MIN};
+use crate::config::{GainBand, KilnConfig};
+use crate::device::Gains;
+
+#[derive(Debug)]
+pub struct PID {
+    k_i: f64,
+    k_p: f64,
+    k_d: f64,
+    last_now: Option<Instant>,
+    i_term: f64,
+    last_measurement: Option<f64>,
+    d_term: f64,
+    /// Bands of gains, lowest temperature first, that take over from the gains above.
+    schedule: Vec<GainBand>,
+    /// Temperature of the band the gains were last taken from.
+    band: Option<f64>,
+    terms: Option<Terms>,
+}
+
+///  A pretty blatant ripoff/rewrite of:
+///    https://github.com/jbruce12000/kiln-controller/blob/master/lib/oven.py#L322
+///
+///  The derivative is taken on the measurement rather than the error, so a jump in the set point
+///    doesn't kick the output, and the integral stops growing while the output is saturated.
+impl PID {
+    pub fn new(config: &KilnConfig) -> PID {
+        PID {
+            k_i: config.integral,
+            k_p: config.proportional,
+            k_d: config.derivative,
+            last_now: None,
+            i_term: 0.0,
+            last_measurement: None,
+            d_term: 0.0,
+            schedule: sorted(&config.gain_schedule),
+            band: None,
+            terms: None,
+        }
+    }
+}
+
+impl Controller for PID {
+    fn compute(&mut self, set_point: f64, is_point: f64, feed_forward: f64) -> f64 {
+        let delta = since(&mut self.last_now);
+
+        self.compute_with_delta(set_point, is_point, feed_forward, delta)
+    }
+
+    fn compute_with_delta(
+        &mut self,
+        set_point: f64,
+        is_point: f64,
+        feed_forward: f64,
+        delta: f64,
+    ) -> f64 {
+        if let Some((gains, band)) = scheduled(&self.schedule, is_point) {
+            // The integral is kept in output units, so changing gains doesn't bump the output.
+            self.set_gains(&gains);
+            self.band = Some(band);
+        }
+
+        let error: f64 = set_point - is_point;
+
+        if delta > 0.0 {
+            if let Some(last_measurement) = self.last_measurement {
+                let d_input = (is_point - last_measurement) / delta;
+                self.d_term += (d_input - self.d_term) * delta / (DERIVATIVE_FILTER + delta);
+            }
+
+            // Only integrates while the output isn't held at a limit by the error already.
+            let i_term = self.i_term + error * delta * self.k_i;
+            let o = (self.k_p * error) + i_term + feed_forward - self.k_d * self.d_term;
+            let winding_up = (o > OUTPUT_MAX && error > 0.0) || (o < OUTPUT_MIN && error < 0.0);
+
+            if !winding_up {
+                // Negative, when the feed forward is more than the kiln needs.
+                self.i_term = i_term.clamp(OUTPUT_MIN - OUTPUT_MAX, OUTPUT_MAX - OUTPUT_MIN);
+            }
+        }
+
+        self.last_measurement = Some(is_point);
+
+        let terms = Terms {
+            proportional: self.k_p * error,
+            integral: self.i_term,
+            derivative: -self.k_d * self.d_term,
+        };
+        self.terms = Some(terms);
+
+        let o: f64 = terms.proportional + terms.integral + feed_forward + terms.derivative;
+        o.clamp(OUTPUT_MIN, OUTPUT_MAX)
+    }
+
+    fn reset(&mut self) {
+        self.idle();
+        self.i_term = 0.0;
+        self.band = None;
+    }
+
+    /// Keeps the integral, so the output picks up where it left off once the kiln heats again
+    ///   after a pause.
+    fn idle(&mut self) {
+        self.last_now = None;
+        self.last_measurement = None;
+        self.d_term = 0.0;
+        self.terms = None;
+    }
+
+    /// Keeps the integral as it was, so the output doesn't jump.
+    fn set_gains(&mut self, gains: &Gains) {
+        self.k_p = gains.proportional;
+        self.k_i = gains.integral;
+        self.k_d = gains.derivative;
+    }
+
+    fn gain_band(&self) -> Option<f64> {
+        self.band
+    }
+
+    fn terms(&self) -> Option<Terms> {
+        self.terms
+    }
+}
+
+fn sorted(bands: &[GainBand]) -> Vec<GainBand> {
+    let mut bands = bands.to_vec();
+    bands.sort_by(|a, b| a.temperature.total_cmp(&b.temperature));
+    bands
+}
+
+/// Gains at `temperature`, interpolated between the bands either side of it, and the temperature
+/// of the band it's in. Below the first band and above the last, their gains are used as they are.
+fn scheduled(bands: &[GainBand], temperature: f64) -> Option<(Gains, f64)> {
+    let (first, last) = (bands.first()?, bands.last()?);
+
+    match bands.iter().position(|band| band.temperature > temperature) {
+        Some(0) => Some((first.gains, first.temperature)),
+        None => Some((last.gains, last.temperature)),
+        Some(upper) => {
+            let (lower, upper) = (&bands[upper - 1], &bands[upper]);
+            let share = (temperature - lower.temperature) / (upper.temperature - lower.temperature);
+            let between = |from: f64, to: f64| from + (to - from) * share;
+
+            let gains = Gains {
+                proportional: between(lower.gains.proportional, upper.gains.proportional),
+                integral: between(lower.gains.integral, upper.gains.integral),
+                derivative: between(lower.gains.derivative, upper.gains.derivative),
+            };
+            Some((gains, lower.temperature))
+        }
+    }
+}
+
+#[cfg(test)]
+mod pid_tests {
+    use super::*;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn pid(k_p: f64, k_i: f64, k_d: f64) -> PID {
+        PID {
+            k_i,
+            k_p,
+            k_d,
+            last_now: None,
+            i_term: 0.0,
+            last_measurement: None,
+            d_term: 0.0,
+            schedule: Vec::new(),
+            band: None,
+            terms: None,
+        }
+    }
+
+    fn band(temperature: f64, proportional: f64) -> GainBand {
+        GainBand {
+            temperature,
+            gains: Gains {
+                proportional,
+                integral: 0.0,
+                derivative: 0.0,
+            },
+        }
+    }
+
+    fn approx(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn should_integrate_over_fractions_of_a_second() {
+        let mut controller = pid(0.0, 0.1, 0.0);
+
+        assert_eq!(controller.compute(110.0, 100.0, 0.0), 0.0);
+        sleep(Duration::from_millis(200));
+        let output = controller.compute(110.0, 100.0, 0.0);
+        assert!(output > 0.15 && output < 0.5, "output was {}", output);
+
+        // No time at all passing doesn't break it either.
+        let output = controller.compute_with_delta(110.0, 100.0, 0.0, 0.0);
+        assert!(output.is_finite());
+
+        let mut controller = pid(0.0, 0.1, 0.0);
+        let output = controller.compute_with_delta(101.0, 100.0, 0.0, 0.5);
+        assert!(approx(output, 0.05));
+    }
+
+    #[test]
+    fn should_not_wind_up_while_saturated() {
+        let mut controller = pid(0.01, 0.1, 0.0);
+
+        for _ in 0..100 {
+            assert_eq!(controller.compute_with_delta(1000.0, 900.0, 0.0, 1.0), 1.0);
+        }
+
+        // Backs off as soon as the set point is passed, with nothing stored up to undo.
+        assert_eq!(controller.compute_with_delta(1000.0, 1001.0, 0.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn should_not_kick_when_the_set_point_jumps() {
+        let mut controller = pid(0.001, 0.0, 100.0);
+
+        controller.compute_with_delta(100.0, 100.0, 0.0, 1.0);
+        let output = controller.compute_with_delta(200.0, 100.0, 0.0, 1.0);
+        assert!(approx(output, 0.1), "output was {}", output);
+    }
+
+    #[test]
+    fn should_filter_the_derivative() {
+        let mut controller = pid(0.01, 0.0, 0.1);
+
+        let output = controller.compute_with_delta(150.0, 100.0, 0.0, 1.0);
+        assert!(approx(output, 0.5));
+
+        // A one degree jump in a second would take 0.1 off unfiltered.
+        let output = controller.compute_with_delta(150.0, 101.0, 0.0, 1.0);
+        assert!(approx(output, 0.49 - 0.1 / (DERIVATIVE_FILTER + 1.0)));
+
+        let terms = controller.terms().unwrap();
+        assert!(approx(terms.proportional, 0.49));
+        assert!(approx(terms.derivative, -0.1 / (DERIVATIVE_FILTER + 1.0)));
+
+        controller.idle();
+        assert!(controller.terms().is_none());
+    }
+
+    #[test]
+    fn should_reset_between_firings() {
+        let mut controller = pid(0.01, 0.01, 0.0);
+
+        for _ in 0..10 {
+            controller.compute_with_delta(120.0, 100.0, 0.0, 1.0);
+        }
+
+        // Picks up where it left off after a pause...
+        controller.idle();
+        let output = controller.compute(120.0, 100.0, 0.0);
+        assert!(approx(output, 0.2 + controller.i_term));
+        assert!(controller.i_term > 0.0);
+
+        // ...but starts from scratch for a new firing.
+        controller.reset();
+        assert!(approx(controller.compute(120.0, 100.0, 0.0), 0.2));
+    }
+
+    #[test]
+    fn should_interpolate_between_bands() {
+        let bands = sorted(&[band(1000.0, 0.01), band(200.0, 0.05)]);
+
+        let (gains, band) = scheduled(&bands, 600.0).unwrap();
+        assert!(approx(gains.proportional, 0.03));
+        assert_eq!(band, 200.0);
+
+        // Held at the ends rather than carried on past them.
+        assert_eq!(scheduled(&bands, 20.0).unwrap(), (bands[0].gains, 200.0));
+        assert_eq!(scheduled(&bands, 1200.0).unwrap(), (bands[1].gains, 1000.0));
+
+        assert!(scheduled(&[], 600.0).is_none());
+    }
+
+    #[test]
+    fn should_use_the_gains_for_the_temperature() {
+        let mut controller = pid(0.02, 0.0, 0.0);
+        // Ten degrees below the set point.
+        let output = |controller: &mut PID, temperature: f64| {
+            controller.compute_with_delta(temperature + 10.0, temperature, 0.0, 1.0)
+        };
+
+        assert!(approx(output(&mut controller, 100.0), 0.2));
+        assert_eq!(controller.gain_band(), None);
+
+        controller.schedule = sorted(&[band(200.0, 0.05), band(1000.0, 0.01)]);
+        assert!(approx(output(&mut controller, 100.0), 0.5));
+        assert!(approx(output(&mut controller, 600.0), 0.3));
+        assert_eq!(controller.gain_band(), Some(200.0));
+        assert!(approx(output(&mut controller, 1000.0), 0.1));
+        assert_eq!(controller.gain_band(), Some(1000.0));
+
+        controller.reset();
+        assert_eq!(controller.gain_band(), None);
+    }
+}
@@ -0,0 +1,309 @@
+//! Time-proportional heater output. Each PWM window the heater is switched on for the share of
+//! the window given by the duty cycle, in a task of its own so the control loop can keep reading
+//! the thermocouple while the heater is on.
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::{self, JoinHandle};
+use tokio::time::{sleep_until, Instant};
+use tracing::error;
+
+use crate::config::KilnConfig;
+use crate::sensor::HeaterOutput;
+
+/// Length of the PWM window and the shortest time the heater can be switched on or off for, in
+/// milliseconds. Solid state relays can switch every window, contactors want longer windows and
+/// minimum times so they aren't worn out by short pulses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pwm {
+    pub window: u32,
+    pub min_on: u32,
+    pub min_off: u32,
+}
+
+impl Pwm {
+    pub fn new(config: &KilnConfig) -> Pwm {
+        Pwm {
+            window: config.pwm_window,
+            min_on: config.min_on_time,
+            min_off: config.min_off_time,
+        }
+    }
+
+    /// Splits a window into heater on and off times for the duty cycle, between 0 and 1. On times
+    /// shorter than `min_on` are dropped, and off times shorter than `min_off` are filled in.
+    pub(super) fn split(&self, duty: f64) -> (Duration, Duration) {
+        let window = self.window as u64;
+        let on = (window as f64 * duty.clamp(0.0, 1.0)).round() as u64;
+        let on = if on < self.min_on as u64 {
+            0
+        } else if on > 0 && window - on < self.min_off as u64 {
+            window
+        } else {
+            on
+        };
+
+        (
+            Duration::from_millis(on),
+            Duration::from_millis(window - on),
+        )
+    }
+}
+
+/// Time the heater has been on, shared between the driver and the control loop.
+#[derive(Debug, Default)]
+struct Meter {
+    total: Duration,
+    since: Option<Instant>,
+}
+
+impl Meter {
+    fn on(&mut self, now: Instant) {
+        if self.since.is_none() {
+            self.since = Some(now);
+        }
+    }
+
+    fn off(&mut self, now: Instant) {
+        if let Some(since) = self.since.take() {
+            self.total += now - since;
+        }
+    }
+
+    /// Time on since the last take, including the time so far if the heater is still on.
+    fn take(&mut self, now: Instant) -> Duration {
+        if let Some(since) = self.since {
+            self.total += now - since;
+            self.since = Some(now);
+        }
+
+        std::mem::take(&mut self.total)
+    }
+}
+
+/// Why waiting in the driver came to an end.
+enum Wake {
+    Elapsed,
+    Changed(f64),
+    Stopped,
+}
+
+/// Runs the heater in a task of its own, following the duty cycle it's given.
+#[derive(Debug)]
+pub struct HeaterDriver {
+    duty: watch::Sender<f64>,
+    meter: Arc<Mutex<Meter>>,
+    task: JoinHandle<()>,
+}
+
+impl HeaterDriver {
+    /// Takes over the heater, starting with it off.
+    pub fn start(heater: Box<dyn HeaterOutput>, pwm: Pwm) -> HeaterDriver {
+        let (duty, receiver) = watch::channel(0.0);
+        let meter = Arc::new(Mutex::new(Meter::default()));
+        let task = task::spawn(drive(heater, pwm, receiver, meter.clone()));
+
+        HeaterDriver { duty, meter, task }
+    }
+
+    /// Sets the share of each window the heater is on, from the next window. Dropping to 0
+    /// switches the heater off straight away, and heating from 0 starts a new window.
+    pub fn set(&self, duty: f64) {
+        self.duty.send_replace(duty);
+    }
+
+    /// Milliseconds the heater was on since the last call.
+    pub fn on_time(&self) -> u64 {
+        let mut meter = self.meter.lock().expect("unable to lock heater meter");
+
+        meter.take(Instant::now()).as_millis() as u64
+    }
+
+    /// Switches the heater off and lets go of it, once the driver has finished.
+    pub async fn stop(self) {
+        drop(self.duty);
+
+        if let Err(error) = self.task.await {
+            error!("heater driver ended badly: {}", error);
+        }
+    }
+}
+
+async fn drive(
+    mut heater: Box<dyn HeaterOutput>,
+    pwm: Pwm,
+    mut duty: watch::Receiver<f64>,
+    meter: Arc<Mutex<Meter>>,
+) {
+    let switch = |heater: &mut Box<dyn HeaterOutput>, on: bool| {
+        let mut meter = meter.lock().expect("unable to lock heater meter");
+
+        if on {
+            heater.on();
+            meter.on(Instant::now());
+        } else {
+            heater.off();
+            meter.off(Instant::now());
+        }
+    };
+
+    heater.off();
+
+    loop {
+        let start = Instant::now();
+        let (on, off) = pwm.split(*duty.borrow_and_update());
+
+        if !on.is_zero() {
+            switch(&mut heater, true);
+
+            loop {
+                match wait_until(start + on, &mut duty).await {
+                    Wake::Elapsed => break,
+                    Wake::Changed(duty) if duty <= 0.0 => break,
+                    Wake::Changed(_) => (),
+                    Wake::Stopped => {
+                        switch(&mut heater, false);
+                        return;
+                    }
+                }
+            }
+
+            switch(&mut heater, false);
+        }
+
+        // A window with the heater off throughout starts over as soon as there's heating to do,
+        // once the heater has been off for long enough.
+        let min_off = Duration::from_millis(pwm.min_off as u64);
+
+        loop {
+            match wait_until(start + on + off, &mut duty).await {
+                Wake::Elapsed => break,
+                Wake::Changed(duty) if on.is_zero() && duty > 0.0 && start.elapsed() >= min_off => {
+                    break
+                }
+                Wake::Changed(_) => (),
+                Wake::Stopped => return,
+            }
+        }
+    }
+}
+
+async fn wait_until(until: Instant, duty: &mut watch::Receiver<f64>) -> Wake {
+    tokio::select! {
+        _ = sleep_until(until) => Wake::Elapsed,
+        changed = duty.changed() => match changed {
+            Ok(()) => Wake::Changed(*duty.borrow_and_update()),
+            Err(_) => Wake::Stopped,
+        },
+    }
+}
+
+#[cfg(test)]
+mod driver_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::time::sleep;
+
+    fn pwm(window: u32, min_on: u32, min_off: u32) -> Pwm {
+        Pwm {
+            window,
+            min_on,
+            min_off,
+        }
+    }
+
+    fn millis(split: (Duration, Duration)) -> (u128, u128) {
+        (split.0.as_millis(), split.1.as_millis())
+    }
+
+    /// Shares whether the heater is on with the test.
+    struct Relay(Arc<AtomicBool>);
+
+    impl HeaterOutput for Relay {
+        fn on(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+
+        fn off(&mut self) {
+            self.0.store(false, Ordering::SeqCst);
+        }
+
+        fn toggle(&mut self) {}
+    }
+
+    #[test]
+    fn should_split_the_window() {
+        assert_eq!(millis(pwm(1000, 0, 0).split(0.25)), (250, 750));
+        assert_eq!(millis(pwm(1000, 0, 0).split(-0.5)), (0, 1000));
+        assert_eq!(millis(pwm(1000, 0, 0).split(1.5)), (1000, 0));
+    }
+
+    #[test]
+    fn should_respect_minimum_times() {
+        let contactor = pwm(10_000, 1000, 2000);
+
+        assert_eq!(millis(contactor.split(0.05)), (0, 10_000));
+        assert_eq!(millis(contactor.split(0.1)), (1000, 9000));
+        assert_eq!(millis(contactor.split(0.85)), (10_000, 0));
+        assert_eq!(millis(contactor.split(0.8)), (8000, 2000));
+
+        // Never on with nothing asked for, however long the minimum off time.
+        assert_eq!(millis(pwm(1000, 0, 2000).split(0.0)), (0, 1000));
+    }
+
+    #[test]
+    fn should_only_switch_fully_on_or_off_when_the_minimums_fill_the_window() {
+        // The configs KilnConfig rejects, where no duty cycle gets both an on and an off time.
+        let no_off = pwm(1000, 0, 1000);
+        assert_eq!(millis(no_off.split(0.01)), (1000, 0));
+        assert_eq!(millis(no_off.split(0.5)), (1000, 0));
+
+        let overlapping = pwm(1000, 600, 600);
+        assert_eq!(millis(overlapping.split(0.55)), (0, 1000));
+        assert_eq!(millis(overlapping.split(0.6)), (1000, 0));
+
+        // Minimums that fill the window exactly still leave one split in between.
+        let exact = pwm(1000, 400, 600);
+        assert_eq!(millis(exact.split(0.35)), (0, 1000));
+        assert_eq!(millis(exact.split(0.4)), (400, 600));
+        assert_eq!(millis(exact.split(0.45)), (1000, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_switch_within_the_window() {
+        let on = Arc::new(AtomicBool::new(false));
+        let driver = HeaterDriver::start(Box::new(Relay(on.clone())), pwm(400, 0, 0));
+        sleep(Duration::from_millis(50)).await;
+
+        // Starts a window straight away rather than waiting out the one with the heater off.
+        driver.set(0.5);
+        sleep(Duration::from_millis(100)).await;
+        assert!(on.load(Ordering::SeqCst), "heater wasn't on");
+        sleep(Duration::from_millis(200)).await;
+        assert!(!on.load(Ordering::SeqCst), "heater wasn't off");
+
+        assert_eq!(driver.on_time(), 200);
+
+        driver.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_switch_off_straight_away() {
+        let on = Arc::new(AtomicBool::new(false));
+        let driver = HeaterDriver::start(Box::new(Relay(on.clone())), pwm(300, 0, 0));
+
+        driver.set(1.0);
+        sleep(Duration::from_millis(350)).await;
+        assert!(on.load(Ordering::SeqCst));
+
+        // Well before the end of the window.
+        driver.set(0.0);
+        sleep(Duration::from_millis(50)).await;
+        assert!(!on.load(Ordering::SeqCst), "heater stayed on");
+
+        driver.set(1.0);
+        driver.stop().await;
+        assert!(!on.load(Ordering::SeqCst), "heater left on after stopping");
+    }
+}
@@ -0,0 +1,277 @@
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::KilnConfig;
+use crate::sensor::TemperatureSensor;
+
+/// Degrees the kiln can climb while the heater is off before the relay is assumed stuck on.
+///   Leaves room for the heat still soaking through the walls just after switching off.
+const RUNAWAY_RISE: f64 = 20.0;
+
+/// Reads of the thermocouple attempted each poll before giving up on that poll.
+const READ_ATTEMPTS: u32 = 3;
+
+/// Polls in a row the thermocouple can fail before it's treated as faulted.
+const FAILED_POLL_BUDGET: u32 = 5;
+
+/// Why the kiln was shut down, where temperatures are in C and times in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum Fault {
+    /// The temperature passed the absolute maximum.
+    OverTemperature { temperature: f64, limit: f64 },
+    /// The kiln fell more than `max_difference` behind the set point and stayed there.
+    Deviation { difference: f64, seconds: u32 },
+    /// The temperature kept climbing with the heater off.
+    Runaway { rise: f64 },
+    /// The thermocouple couldn't be read for FAILED_POLL_BUDGET polls in a row.
+    Thermocouple { error: String },
+    /// Autotuning around `target` didn't settle into an oscillation within `seconds`.
+    TuningTimeout { target: f64, seconds: u32 },
+}
+
+/// Watches every reading for conditions where the heater has to be forced off.
+#[derive(Debug)]
+pub struct Safety {
+    max_temperature: f64,
+    max_difference: f64,
+    max_difference_time: u32,
+    /// Milliseconds the kiln has been more than max_difference behind the set point.
+    behind_for: u32,
+    /// Lowest temperature since the heater was last on.
+    off_minimum: Option<f64>,
+    /// Polls in a row the thermocouple couldn't be read.
+    failed_polls: u32,
+}
+
+impl Safety {
+    pub fn new(config: &KilnConfig) -> Safety {
+        Safety {
+            max_temperature: config.max_temp,
+            max_difference: config.max_difference as f64,
+            max_difference_time: config.max_difference_time,
+            behind_for: 0,
+            off_minimum: None,
+            failed_polls: 0,
+        }
+    }
+
+    /// Reads the thermocouple, retrying failed reads. Gives no temperature if the poll's reads
+    ///   all failed, and a fault once too many polls have failed in a row.
+    pub fn read(&mut self, thermocouple: &mut dyn TemperatureSensor) -> Result<Option<f64>, Fault> {
+        let mut attempt = 1;
+
+        loop {
+            match thermocouple.read() {
+                Ok(temperature) => {
+                    self.failed_polls = 0;
+                    return Ok(Some(temperature));
+                }
+                Err(error) if attempt == READ_ATTEMPTS => {
+                    self.failed_polls += 1;
+                    warn!(
+                        failed_polls = self.failed_polls,
+                        "unable to read thermocouple: {}", error
+                    );
+
+                    return if self.failed_polls >= FAILED_POLL_BUDGET {
+                        Err(Fault::Thermocouple {
+                            error: error.to_string(),
+                        })
+                    } else {
+                        Ok(None)
+                    };
+                }
+                Err(_) => attempt += 1,
+            }
+        }
+    }
+
+    /// Checks a reading taken after a poll interval of `interval` milliseconds, in which the
+    ///   heater was on for `on_time` milliseconds. The set point is only given while a schedule
+    ///   is running.
+    ///
+    /// Only falling behind the set point counts as a deviation. A kiln above the set point with
+    ///   the heater off is just cooling slower than the schedule, which is reported as a warning,
+    ///   and one that keeps getting hotter anyway is caught by the runaway check.
+    pub fn check(
+        &mut self,
+        temperature: f64,
+        set_point: Option<f64>,
+        on_time: u64,
+        interval: u32,
+    ) -> Option<Fault> {
+        if temperature > self.max_temperature {
+            return Some(Fault::OverTemperature {
+                temperature,
+                limit: self.max_temperature,
+            });
+        }
+
+        let difference = set_point.map_or(0.0, |set_point| set_point - temperature);
+        if difference > self.max_difference {
+            self.behind_for += interval;
+        } else {
+            self.behind_for = 0;
+        }
+
+        if self.behind_for / 1000 > self.max_difference_time {
+            return Some(Fault::Deviation {
+                difference,
+                seconds: self.behind_for / 1000,
+            });
+        }
+
+        if on_time > 0 {
+            self.off_minimum = None;
+        } else {
+            let minimum = self.off_minimum.map_or(temperature, |m| m.min(temperature));
+            self.off_minimum = Some(minimum);
+
+            if temperature - minimum > RUNAWAY_RISE {
+                return Some(Fault::Runaway {
+                    rise: temperature - minimum,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Forgets the history the checks were built on, after the fault has been dealt with.
+    pub fn reset(&mut self) {
+        self.behind_for = 0;
+        self.off_minimum = None;
+        self.failed_polls = 0;
+    }
+}
+
+#[cfg(test)]
+mod safety_tests {
+    use super::*;
+    use crate::sensor::thermocouple::ThermocoupleError;
+
+    /// Fails the given number of reads before reading 100C.
+    struct FlakySensor(u32);
+
+    impl TemperatureSensor for FlakySensor {
+        fn read(&mut self) -> Result<f64, ThermocoupleError> {
+            if self.0 > 0 {
+                self.0 -= 1;
+                Err(ThermocoupleError::OpenCircuit)
+            } else {
+                Ok(100.0)
+            }
+        }
+
+        fn read_internal(&mut self) -> Result<f64, ThermocoupleError> {
+            Ok(25.0)
+        }
+    }
+
+    fn safety() -> Safety {
+        Safety::new(&KilnConfig {
+            max_difference_time: 60,
+            ..KilnConfig::default()
+        })
+    }
+
+    #[test]
+    fn should_trip_over_temperature() {
+        let mut safety = safety();
+
+        assert_eq!(safety.check(1299.0, None, 0, 1000), None);
+        assert_eq!(
+            safety.check(1301.0, None, 0, 1000),
+            Some(Fault::OverTemperature {
+                temperature: 1301.0,
+                limit: 1300.0
+            })
+        );
+    }
+
+    #[test]
+    fn should_trip_when_falling_behind_for_too_long() {
+        let mut safety = safety();
+
+        for _ in 0..60 {
+            assert_eq!(safety.check(100.0, Some(200.0), 1000, 1000), None);
+        }
+
+        // Catching up starts the count over.
+        assert_eq!(safety.check(190.0, Some(200.0), 1000, 1000), None);
+        for _ in 0..60 {
+            assert_eq!(safety.check(100.0, Some(200.0), 1000, 1000), None);
+        }
+
+        assert_eq!(
+            safety.check(100.0, Some(200.0), 1000, 1000),
+            Some(Fault::Deviation {
+                difference: 100.0,
+                seconds: 61
+            })
+        );
+    }
+
+    #[test]
+    fn should_not_trip_when_ahead_of_the_set_point() {
+        let mut safety = safety();
+
+        for _ in 0..120 {
+            assert_eq!(safety.check(300.0, Some(200.0), 0, 1000), None);
+        }
+    }
+
+    #[test]
+    fn should_trip_when_heating_with_the_heater_off() {
+        let mut safety = safety();
+
+        // Heating while the heater is on is expected.
+        for t in 0..50 {
+            assert_eq!(safety.check(100.0 + t as f64, None, 1000, 1000), None);
+        }
+
+        // Some overshoot after switching off, then cooling.
+        assert_eq!(safety.check(150.0, None, 0, 1000), None);
+        assert_eq!(safety.check(155.0, None, 0, 1000), None);
+        assert_eq!(safety.check(140.0, None, 0, 1000), None);
+
+        assert_eq!(
+            safety.check(165.0, None, 0, 1000),
+            Some(Fault::Runaway { rise: 25.0 })
+        );
+    }
+
+    #[test]
+    fn should_retry_failed_reads() {
+        let mut safety = safety();
+        let mut sensor = FlakySensor(READ_ATTEMPTS - 1);
+
+        assert_eq!(safety.read(&mut sensor), Ok(Some(100.0)));
+    }
+
+    #[test]
+    fn should_fault_once_the_budget_is_spent() {
+        let mut safety = safety();
+        let mut sensor = FlakySensor(READ_ATTEMPTS * (FAILED_POLL_BUDGET - 1));
+
+        for _ in 1..FAILED_POLL_BUDGET {
+            assert_eq!(safety.read(&mut sensor), Ok(None));
+        }
+
+        // A good read refills the budget.
+        assert_eq!(safety.read(&mut sensor), Ok(Some(100.0)));
+
+        let mut sensor = FlakySensor(u32::MAX);
+        for _ in 1..FAILED_POLL_BUDGET {
+            assert_eq!(safety.read(&mut sensor), Ok(None));
+        }
+
+        assert_eq!(
+            safety.read(&mut sensor),
+            Err(Fault::Thermocouple {
+                error: ThermocoupleError::OpenCircuit.to_string()
+            })
+        );
+    }
+}
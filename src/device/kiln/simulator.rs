@@ -0,0 +1,137 @@
+//! Fires a schedule against the simulated kiln on a virtual clock, as fast as the CPU allows.
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use super::autotune::Gains;
+use super::controller;
+use super::driver::Pwm;
+use super::{control, KilnState, RunState, Warning};
+use crate::config::KilnConfig;
+use crate::schedule::NormalizedSchedule;
+use crate::sensor::simulation::{SharedModel, SimulationConfig, ThermalModel};
+use crate::sensor::{SimulatedHeater, SimulatedMCP9600};
+
+/// A single iteration of the simulated kiln loop, where
+/// temperature and set_point: in C
+/// runtime: time the schedule has been running in seconds
+/// output: controller output, between 0 and 1
+/// warning: what the kiln couldn't keep up with at the time
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TracePoint {
+    pub runtime: u32,
+    pub temperature: f64,
+    pub set_point: f64,
+    pub output: f64,
+    pub warning: Option<Warning>,
+}
+
+/// Runs the whole schedule, polling every `interval` milliseconds of virtual time, and returns
+/// the temperature and set point at every poll. The heater is switched once a poll, keeping to
+/// the kiln's minimum on and off times.
+pub fn simulate(
+    schedule: NormalizedSchedule,
+    config: &KilnConfig,
+    simulation: SimulationConfig,
+    interval: u32,
+) -> Result<Vec<TracePoint>> {
+    let model: SharedModel = Arc::new(Mutex::new(ThermalModel::accelerated(simulation)));
+    let mut thermocouple = SimulatedMCP9600::with_model(0, model.clone());
+    let mut heater = SimulatedHeater::with_model(0, model.clone());
+    let mut controller = controller::from_config(config);
+    let pwm = Pwm {
+        window: interval,
+        ..Pwm::new(config)
+    };
+    let mut run = RunState::default();
+    let mut trace = Vec::new();
+
+    run.start(schedule);
+
+    while run.state == KilnState::Running {
+        let temperature = thermocouple.read()?;
+        let poll = control(
+            &mut run,
+            controller.as_mut(),
+            config,
+            Some(temperature),
+            interval,
+            Some(interval as f64 / 1000.0),
+        );
+        let (on_time, off_time) = pwm.split(poll.duty);
+
+        heater.on();
+        advance(&model, on_time);
+        heater.off();
+        advance(&model, off_time);
+
+        trace.push(TracePoint {
+            runtime: run.runtime,
+            temperature,
+            set_point: poll.set_point,
+            output: poll.output,
+            warning: poll.warning,
+        });
+
+        if poll.driving && run.advance(interval) {
+            run.stop();
+        }
+    }
+
+    Ok(trace)
+}
+
+/// Autotunes the simulated kiln around `target`, polling every `interval` milliseconds of virtual
+/// time, and returns the gains it comes up with.
+pub fn simulate_autotune(
+    target: f64,
+    simulation: SimulationConfig,
+    interval: u32,
+) -> Result<Gains> {
+    let model: SharedModel = Arc::new(Mutex::new(ThermalModel::accelerated(simulation)));
+    let mut thermocouple = SimulatedMCP9600::with_model(0, model.clone());
+    let mut heater = SimulatedHeater::with_model(0, model.clone());
+    let config = KilnConfig::default();
+    let mut controller = controller::from_config(&config);
+    let pwm = Pwm {
+        window: interval,
+        ..Pwm::new(&config)
+    };
+    let mut run = RunState::default();
+
+    run.tune(target);
+
+    while run.overdue().is_none() {
+        let temperature = thermocouple.read()?;
+        let poll = control(
+            &mut run,
+            controller.as_mut(),
+            &config,
+            Some(temperature),
+            interval,
+            Some(interval as f64 / 1000.0),
+        );
+        let (on_time, off_time) = pwm.split(poll.duty);
+
+        heater.on();
+        advance(&model, on_time);
+        heater.off();
+        advance(&model, off_time);
+
+        if let Some(gains) = poll.tuned {
+            return Ok(gains);
+        }
+    }
+
+    Err(anyhow!("kiln never settled around {}C", target))
+}
+
+fn advance(model: &SharedModel, time: Duration) {
+    model
+        .lock()
+        .expect("unable to lock kiln model")
+        .advance(time.as_secs_f64());
+}
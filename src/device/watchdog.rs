@@ -0,0 +1,61 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Written before closing to tell the kernel the watchdog is being stopped on purpose.
+const MAGIC_CLOSE: &[u8] = b"V";
+
+/// Keeps a watchdog timer from rebooting the system while the kiln loop is alive.
+///
+/// Linux watchdog devices, like `/dev/watchdog`, reboot the system unless written to within
+///   their timeout, so the poll interval has to be shorter than the timeout. Any other file works
+///   as a stand-in, collecting one byte per pet.
+#[derive(Debug)]
+pub struct Watchdog {
+    file: File,
+}
+
+impl Watchdog {
+    /// Opens the device, arming the timer.
+    pub fn open<P: AsRef<Path>>(device: P) -> io::Result<Watchdog> {
+        let file = OpenOptions::new().create(true).append(true).open(device)?;
+
+        Ok(Watchdog { file })
+    }
+
+    /// Restarts the timer.
+    pub fn pet(&mut self) -> io::Result<()> {
+        self.file.write_all(b"1")?;
+        self.file.flush()
+    }
+
+    /// Stops the timer, for a clean shutdown. If the watchdog is dropped without this, the
+    ///   timer keeps running and the system reboots.
+    pub fn disarm(mut self) -> io::Result<()> {
+        self.file.write_all(MAGIC_CLOSE)?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod watchdog_tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn should_pet_and_disarm() -> io::Result<()> {
+        let dir = tempdir()?;
+        let device = dir.path().join("watchdog");
+        let mut watchdog = Watchdog::open(&device)?;
+
+        watchdog.pet()?;
+        watchdog.pet()?;
+        assert_eq!(fs::read_to_string(&device)?, "11");
+
+        watchdog.disarm()?;
+        assert_eq!(fs::read_to_string(&device)?, "11V");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+mod error;
+pub use error::FiringError;
+
+mod export;
+pub use export::{export, ExportFormat};
+
+mod pending;
+pub use pending::PendingStart;
+
+mod record;
+pub use record::*;
@@ -0,0 +1,49 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result},
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub enum FiringError {
+    IOError { description: String },
+    InvalidJson { description: String },
+    InvalidId(String),
+    InvalidFormat(String),
+    NotRecording,
+}
+
+impl Display for FiringError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            FiringError::IOError { description } => write!(f, "error reading {}", description),
+            FiringError::InvalidJson { description } => {
+                write!(f, "error reading json: {}", description)
+            }
+            FiringError::InvalidId(id) => write!(f, "invalid firing id [{}]", id),
+            FiringError::InvalidFormat(format) => {
+                write!(f, "unknown export format [{}]", format)
+            }
+            FiringError::NotRecording => write!(f, "no firing is being recorded"),
+        }
+    }
+}
+
+impl Error for FiringError {}
+
+impl From<std::io::Error> for FiringError {
+    fn from(error: std::io::Error) -> FiringError {
+        FiringError::IOError {
+            description: format!("{:?}", error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for FiringError {
+    fn from(error: serde_json::Error) -> FiringError {
+        FiringError::InvalidJson {
+            description: format!("{}", error),
+        }
+    }
+}
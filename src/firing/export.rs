@@ -0,0 +1,152 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::error::FiringError;
+use super::record::Sample;
+
+const CSV_HEADER: &str = "timestamp,runtime,temperature,set_point,output,on_time,duty_cycle";
+
+/// Formats a firing's samples can be exported as.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Comma separated values, with a header row.
+    Csv,
+    /// Newline delimited json, one sample per line.
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "jsonl",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = FiringError;
+
+    fn from_str(input: &str) -> Result<ExportFormat, Self::Err> {
+        match input {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(FiringError::InvalidFormat(input.to_string())),
+        }
+    }
+}
+
+/// Writes every sample to the writer in the given format.
+pub fn export<W: Write>(
+    samples: &[Sample],
+    format: ExportFormat,
+    writer: &mut W,
+) -> Result<(), FiringError> {
+    match format {
+        ExportFormat::Csv => {
+            writeln!(writer, "{}", CSV_HEADER)?;
+
+            for sample in samples {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    sample.timestamp.to_rfc3339(),
+                    sample.runtime,
+                    sample.temperature,
+                    sample.set_point,
+                    sample.output,
+                    sample.on_time,
+                    sample.duty_cycle
+                )?;
+            }
+        }
+        ExportFormat::Json => {
+            for sample in samples {
+                writeln!(writer, "{}", serde_json::to_string(sample)?)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+    use anyhow::Result;
+    use chrono::{TimeZone, Utc};
+
+    fn samples() -> Vec<Sample> {
+        vec![
+            Sample {
+                timestamp: Utc.timestamp_opt(0, 0).unwrap(),
+                runtime: 0,
+                temperature: 25.0,
+                set_point: 25.0,
+                output: 0.0,
+                on_time: 0,
+                duty_cycle: 0.0,
+            },
+            Sample {
+                timestamp: Utc.timestamp_opt(10, 0).unwrap(),
+                runtime: 10,
+                temperature: 25.5,
+                set_point: 26.0,
+                output: 0.25,
+                on_time: 2500,
+                duty_cycle: 0.25,
+            },
+        ]
+    }
+
+    #[test]
+    fn should_export_csv() -> Result<()> {
+        let mut output = Vec::new();
+
+        export(&samples(), ExportFormat::Csv, &mut output)?;
+
+        let output = String::from_utf8(output)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[2],
+            "1970-01-01T00:00:10+00:00,10,25.5,26,0.25,2500,0.25"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_export_json_lines() -> Result<()> {
+        let mut output = Vec::new();
+
+        export(&samples(), ExportFormat::Json, &mut output)?;
+
+        let output = String::from_utf8(output)?;
+        let parsed = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Sample>, serde_json::Error>>()?;
+        assert_eq!(parsed, samples());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_formats() {
+        assert_eq!(ExportFormat::from_str("csv").unwrap(), ExportFormat::Csv);
+        assert_eq!(ExportFormat::from_str("json").unwrap(), ExportFormat::Json);
+        assert!(ExportFormat::from_str("xml").is_err());
+    }
+}
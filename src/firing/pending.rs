@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::error::FiringError;
+use crate::device::StartFrom;
+use crate::schedule::NormalizedSchedule;
+
+const PENDING_FILE: &str = "pending.json";
+
+/// A firing waiting for its start time. Kept in the firings folder so it survives a restart.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingStart {
+    pub schedule: NormalizedSchedule,
+    pub from: StartFrom,
+    pub at: DateTime<Utc>,
+}
+
+impl PendingStart {
+    /// The start waiting in the firings folder, if there is one.
+    pub fn load(firings_directory: &str) -> Result<Option<PendingStart>, FiringError> {
+        let path = Path::new(firings_directory).join(PENDING_FILE);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(path)?;
+        Ok(Some(serde_json::from_str(content.as_str())?))
+    }
+
+    pub fn save(&self, firings_directory: &str) -> Result<(), FiringError> {
+        let directory = Path::new(firings_directory);
+        let partial = directory.join(format!("{}.partial", PENDING_FILE));
+
+        fs::create_dir_all(directory)?;
+        fs::write(&partial, serde_json::to_string(self)?)?;
+        fs::rename(&partial, directory.join(PENDING_FILE))?;
+
+        Ok(())
+    }
+
+    /// Forgets the waiting start, whether or not there is one.
+    pub fn clear(firings_directory: &str) -> Result<(), FiringError> {
+        let path = Path::new(firings_directory).join(PENDING_FILE);
+
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod pending_tests {
+    use super::*;
+    use crate::firing::Firing;
+    use crate::schedule::Schedule;
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    #[test]
+    fn should_save_load_and_clear() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap();
+        let pending = PendingStart {
+            schedule: Schedule::from_file("./tests/sample_schedules/valid.yaml".to_string())?
+                .normalize()?,
+            from: StartFrom::Temperature,
+            at: Utc::now(),
+        };
+
+        assert!(PendingStart::load(directory)?.is_none());
+
+        pending.save(directory)?;
+        let loaded = PendingStart::load(directory)?.unwrap();
+        assert_eq!(loaded.at, pending.at);
+        assert_eq!(loaded.from, StartFrom::Temperature);
+
+        // Not mistaken for a firing.
+        assert!(Firing::all(directory)?.is_empty());
+
+        PendingStart::clear(directory)?;
+        PendingStart::clear(directory)?;
+        assert!(PendingStart::load(directory)?.is_none());
+
+        dir.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,503 @@
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::{prelude::*, BufReader};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::error::FiringError;
+use crate::device::PauseMode;
+use crate::schedule::NormalizedSchedule;
+
+const FIRING_FILE: &str = "firing.json";
+const SAMPLES_FILE: &str = "samples.jsonl";
+const CHECKPOINT_FILE: &str = "checkpoint.json";
+const EVENTS_FILE: &str = "events.jsonl";
+
+/// Seconds between checkpoints of the firing in progress.
+const CHECKPOINT_INTERVAL: i64 = 10;
+
+/// Why a firing came to an end.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CompletionReason {
+    /// The schedule ran to the end.
+    Complete,
+    /// The schedule was stopped before the end.
+    Stopped,
+    /// The safety interlock shut the kiln down.
+    Fault,
+}
+
+/// A single reading taken during a firing, where
+/// temperature and set_point: recorded temperature in C
+/// runtime: time the schedule has been running in seconds
+/// output: controller output, between 0 and 1
+/// on_time: time the heater was on during the poll interval in milliseconds
+/// duty_cycle: fraction of the poll interval the heater was on
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sample {
+    pub timestamp: DateTime<Utc>,
+    pub runtime: u32,
+    pub temperature: f64,
+    pub set_point: f64,
+    #[serde(default)]
+    pub output: f64,
+    #[serde(default)]
+    pub on_time: u64,
+    pub duty_cycle: f64,
+}
+
+/// Something done to a firing while it ran, where
+/// runtime: time the schedule had been running in seconds when it happened
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FiringEvent {
+    pub timestamp: DateTime<Utc>,
+    pub runtime: u32,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum EventKind {
+    Paused {
+        mode: PauseMode,
+    },
+    Resumed,
+    /// The schedule's clock was moved to the start of step `to`, where a `to` past the last step
+    /// is the end of the schedule.
+    Skipped {
+        from: usize,
+        to: usize,
+    },
+    /// The steps from `from` on were replaced with `steps`, cutting the step before short at the
+    /// time of the edit.
+    Edited {
+        from: usize,
+        steps: Vec<String>,
+    },
+}
+
+/// A single run of a schedule. The end time and reason are empty while it is in progress.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Firing {
+    pub id: String,
+    pub schedule: NormalizedSchedule,
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub reason: Option<CompletionReason>,
+}
+
+/// How far through the schedule a firing had got, where
+/// runtime: time the schedule has been running in seconds
+/// timestamp: when the runtime was written
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub runtime: u32,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A firing along with every sample and event recorded during it.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FiringRecord {
+    #[serde(flatten)]
+    pub firing: Firing,
+    pub samples: Vec<Sample>,
+    pub events: Vec<FiringEvent>,
+}
+
+impl Firing {
+    /// Every recorded firing, most recent first.
+    pub fn all(firings_directory: &str) -> Result<Vec<Firing>, FiringError> {
+        if !Path::new(firings_directory).exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut firings: Vec<Firing> = fs::read_dir(firings_directory)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Firing::read(&entry.path()).ok())
+            .collect();
+
+        firings.sort_by_key(|firing| Reverse(firing.start_time));
+        Ok(firings)
+    }
+
+    pub fn by_id(id: &str, firings_directory: &str) -> Result<FiringRecord, FiringError> {
+        let location = Firing::location(id, firings_directory)?;
+        let firing = Firing::read(&location)?;
+        let samples = Firing::read_lines(&location.join(SAMPLES_FILE))?;
+        let events = Firing::read_lines(&location.join(EVENTS_FILE))?;
+
+        Ok(FiringRecord {
+            firing,
+            samples,
+            events,
+        })
+    }
+
+    /// The most recent firing, if it never finished, along with its last checkpoint.
+    pub fn unfinished(
+        firings_directory: &str,
+    ) -> Result<Option<(Firing, Checkpoint)>, FiringError> {
+        let firing = match Firing::all(firings_directory)?.into_iter().next() {
+            Some(firing) if firing.reason.is_none() => firing,
+            _ => return Ok(None),
+        };
+        let location = Path::new(firings_directory).join(&firing.id);
+        let checkpoint = match fs::read_to_string(location.join(CHECKPOINT_FILE)) {
+            Ok(content) => serde_json::from_str(content.as_str())?,
+            Err(_) => Checkpoint {
+                runtime: 0,
+                timestamp: firing.start_time,
+            },
+        };
+
+        Ok(Some((firing, checkpoint)))
+    }
+
+    /// Folder holding the firing with the given id. Only ids that could have been generated by
+    ///   the recorder are accepted.
+    fn location(id: &str, firings_directory: &str) -> Result<PathBuf, FiringError> {
+        match Uuid::parse_str(id) {
+            Ok(_) => Ok(Path::new(firings_directory).join(id)),
+            Err(_) => Err(FiringError::InvalidId(id.to_string())),
+        }
+    }
+
+    fn read(location: &Path) -> Result<Firing, FiringError> {
+        let content = fs::read_to_string(location.join(FIRING_FILE))?;
+
+        Ok(serde_json::from_str(content.as_str())?)
+    }
+
+    /// Reads back samples or events, skipping any line cut short by a crash mid-write.
+    fn read_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, FiringError> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            if let Ok(entry) = serde_json::from_str(line?.as_str()) {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn write(&self, location: &Path) -> Result<(), FiringError> {
+        fs::write(location.join(FIRING_FILE), serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+/// Writes the firing in progress to disk as it happens.
+#[derive(Debug)]
+pub struct Recorder {
+    directory: PathBuf,
+    current: Option<(Firing, File)>,
+    checkpointed: Option<DateTime<Utc>>,
+}
+
+impl Recorder {
+    pub fn new(firings_directory: &str) -> Recorder {
+        Recorder {
+            directory: PathBuf::from(firings_directory),
+            current: None,
+            checkpointed: None,
+        }
+    }
+
+    /// Begins recording a new firing of the schedule, returning its id.
+    pub fn start(&mut self, schedule: &NormalizedSchedule) -> Result<String, FiringError> {
+        let firing = Firing {
+            id: Uuid::new_v4().to_string(),
+            schedule: schedule.clone(),
+            start_time: Utc::now(),
+            end_time: None,
+            reason: None,
+        };
+        let location = self.directory.join(&firing.id);
+
+        fs::create_dir_all(&location)?;
+        firing.write(&location)?;
+
+        let id = firing.id.clone();
+
+        self.resume(firing)?;
+        self.checkpoint(0)?;
+        Ok(id)
+    }
+
+    /// Carries on recording a firing that was interrupted.
+    pub fn resume(&mut self, firing: Firing) -> Result<(), FiringError> {
+        let samples = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join(&firing.id).join(SAMPLES_FILE))?;
+
+        self.current = Some((firing, samples));
+        self.checkpointed = None;
+        Ok(())
+    }
+
+    /// Writes how far through the schedule the firing is, so it can be resumed after a restart.
+    /// Skipped if the last checkpoint was written less than CHECKPOINT_INTERVAL seconds ago.
+    pub fn checkpoint(&mut self, runtime: u32) -> Result<(), FiringError> {
+        let now = Utc::now();
+
+        if let Some(last) = self.checkpointed {
+            if now - last < Duration::seconds(CHECKPOINT_INTERVAL) {
+                return Ok(());
+            }
+        }
+
+        match &self.current {
+            Some((firing, _)) => {
+                let location = self.directory.join(&firing.id);
+                let checkpoint = Checkpoint {
+                    runtime,
+                    timestamp: now,
+                };
+                let partial = location.join(format!("{}.partial", CHECKPOINT_FILE));
+
+                // Renamed into place so a power cut can't leave half a checkpoint behind.
+                fs::write(&partial, serde_json::to_string(&checkpoint)?)?;
+                fs::rename(&partial, location.join(CHECKPOINT_FILE))?;
+
+                self.checkpointed = Some(now);
+                Ok(())
+            }
+            None => Err(FiringError::NotRecording),
+        }
+    }
+
+    /// Swaps the schedule being fired for one that was changed while running.
+    pub fn amend(&mut self, schedule: &NormalizedSchedule) -> Result<(), FiringError> {
+        match &mut self.current {
+            Some((firing, _)) => {
+                firing.schedule = schedule.clone();
+                firing.write(&self.directory.join(&firing.id))
+            }
+            None => Err(FiringError::NotRecording),
+        }
+    }
+
+    pub fn record(&mut self, sample: &Sample) -> Result<(), FiringError> {
+        match &mut self.current {
+            Some((_, samples)) => {
+                writeln!(samples, "{}", serde_json::to_string(sample)?)?;
+                Ok(())
+            }
+            None => Err(FiringError::NotRecording),
+        }
+    }
+
+    /// Adds to the firing's event log, `runtime` seconds into the schedule.
+    pub fn log(&mut self, runtime: u32, kind: EventKind) -> Result<(), FiringError> {
+        match &self.current {
+            Some((firing, _)) => {
+                let event = FiringEvent {
+                    timestamp: Utc::now(),
+                    runtime,
+                    kind,
+                };
+                let mut events = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.directory.join(&firing.id).join(EVENTS_FILE))?;
+
+                writeln!(events, "{}", serde_json::to_string(&event)?)?;
+                Ok(())
+            }
+            None => Err(FiringError::NotRecording),
+        }
+    }
+
+    pub fn finish(&mut self, reason: CompletionReason) -> Result<(), FiringError> {
+        match self.current.take() {
+            Some((mut firing, _)) => {
+                firing.end_time = Some(Utc::now());
+                firing.reason = Some(reason);
+                firing.write(&self.directory.join(&firing.id))
+            }
+            None => Err(FiringError::NotRecording),
+        }
+    }
+}
+
+#[cfg(test)]
+mod record_tests {
+    use super::*;
+    use crate::schedule::Schedule;
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    fn schedule() -> NormalizedSchedule {
+        Schedule::from_file("./tests/sample_schedules/valid.yaml".to_string())
+            .unwrap()
+            .normalize()
+            .unwrap()
+    }
+
+    fn sample(runtime: u32) -> Sample {
+        Sample {
+            timestamp: Utc::now(),
+            runtime,
+            temperature: 25.0,
+            set_point: 30.0,
+            output: 0.5,
+            on_time: 500,
+            duty_cycle: 0.5,
+        }
+    }
+
+    #[test]
+    fn should_record_a_firing() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap();
+        let mut recorder = Recorder::new(directory);
+
+        let id = recorder.start(&schedule())?;
+        recorder.record(&sample(0))?;
+        recorder.record(&sample(10))?;
+        recorder.finish(CompletionReason::Complete)?;
+
+        let firings = Firing::all(directory)?;
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].id, id);
+
+        let record = Firing::by_id(&id, directory)?;
+        assert_eq!(record.firing.reason, Some(CompletionReason::Complete));
+        assert!(record.firing.end_time.is_some());
+        assert_eq!(record.samples.len(), 2);
+        assert_eq!(record.samples[1].runtime, 10);
+        assert!(record.events.is_empty());
+
+        dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_firings_in_progress() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap();
+        let mut recorder = Recorder::new(directory);
+
+        let id = recorder.start(&schedule())?;
+
+        let record = Firing::by_id(&id, directory)?;
+        assert_eq!(record.firing.reason, None);
+        assert!(record.samples.is_empty());
+
+        dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_unfinished_firings() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap();
+        let mut recorder = Recorder::new(directory);
+
+        assert!(Firing::unfinished(directory)?.is_none());
+
+        let id = recorder.start(&schedule())?;
+
+        let (firing, checkpoint) = Firing::unfinished(directory)?.unwrap();
+        assert_eq!(firing.id, id);
+        assert_eq!(checkpoint.runtime, 0);
+
+        // Checkpoints are throttled, the restarted recorder writes the next one straight away.
+        recorder.checkpoint(10)?;
+        assert_eq!(Firing::unfinished(directory)?.unwrap().1.runtime, 0);
+
+        let mut restarted = Recorder::new(directory);
+        restarted.resume(firing)?;
+        restarted.checkpoint(20)?;
+        restarted.record(&sample(20))?;
+        assert_eq!(Firing::unfinished(directory)?.unwrap().1.runtime, 20);
+
+        restarted.finish(CompletionReason::Complete)?;
+        assert!(Firing::unfinished(directory)?.is_none());
+        assert_eq!(Firing::by_id(&id, directory)?.samples.len(), 1);
+
+        dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_log_events() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap();
+        let mut recorder = Recorder::new(directory);
+
+        let id = recorder.start(&schedule())?;
+        recorder.log(60, EventKind::Skipped { from: 0, to: 1 })?;
+        recorder.log(
+            90,
+            EventKind::Paused {
+                mode: PauseMode::Off,
+            },
+        )?;
+        recorder.finish(CompletionReason::Stopped)?;
+
+        let events = Firing::by_id(&id, directory)?.events;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].runtime, 60);
+        assert_eq!(events[0].kind, EventKind::Skipped { from: 0, to: 1 });
+
+        let json = serde_json::to_string(&events[1])?;
+        assert!(json.contains(r#""type":"paused","mode":"off""#), "{}", json);
+
+        // Nothing to log to once the firing is over.
+        assert!(recorder.log(120, EventKind::Resumed).is_err());
+
+        dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_amend_the_schedule_being_fired() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap();
+        let mut recorder = Recorder::new(directory);
+        let mut schedule = schedule();
+
+        let id = recorder.start(&schedule)?;
+        schedule.steps.truncate(1);
+        recorder.amend(&schedule)?;
+
+        let (firing, _) = Firing::unfinished(directory)?.unwrap();
+        assert_eq!(firing.id, id);
+        assert_eq!(firing.schedule.steps.len(), 1);
+
+        dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_ids_outside_the_firings_folder() {
+        let result = Firing::by_id("../schedules", "./firings");
+
+        assert!(matches!(result, Err(FiringError::InvalidId(_))));
+    }
+
+    #[test]
+    fn should_not_record_without_a_firing() {
+        let mut recorder = Recorder::new("./firings");
+
+        assert!(recorder.record(&sample(0)).is_err());
+        assert!(recorder.finish(CompletionReason::Stopped).is_err());
+    }
+}
@@ -2,6 +2,7 @@ mod config;
 pub use config::*;
 
 pub mod device;
+pub mod firing;
 pub mod schedule;
 pub mod sensor;
 
@@ -1,5 +1,9 @@
+use std::fs::File;
+use std::io;
+
+use caminatus::firing::{export, Firing};
 use caminatus::server::Manager;
-use caminatus::Config;
+use caminatus::{Config, Subcommand};
 
 use anyhow::Result;
 
@@ -7,7 +11,19 @@ use anyhow::Result;
 async fn main() -> Result<()> {
     let conf = Config::init()?;
 
-    Manager::start(conf).await?;
+    match &conf.command {
+        Some(Subcommand::Export { id, format, output }) => {
+            let record = Firing::by_id(id, &conf.firings_folder)?;
+
+            match output {
+                Some(path) => export(&record.samples, *format, &mut File::create(path)?)?,
+                None => export(&record.samples, *format, &mut io::stdout())?,
+            }
+        }
+        None => {
+            Manager::start(conf).await?;
+        }
+    }
 
     Ok(())
 }
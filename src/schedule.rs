@@ -1,5 +1,8 @@
 mod error;
 pub use error::ScheduleError;
 
+mod cone;
+pub use cone::cone_temperature;
+
 mod parser;
 pub use parser::*;
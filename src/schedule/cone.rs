@@ -0,0 +1,101 @@
+//! Orton cones, which bend once the ware has taken in enough heat-work. How hot that is depends on
+//! how quickly the kiln climbed at the end, so a cone is only a temperature given the final ramp.
+use anyhow::{anyhow, Result};
+
+/// Final heating rates, in C per hour, the cone chart is given at.
+const RATES: [f64; 3] = [15.0, 60.0, 150.0];
+
+/// Temperatures, in C, self-supporting Orton cones bend at, for each of the rates above.
+const CONES: [(&str, [f64; 3]); 35] = [
+    ("019", [656.0, 678.0, 695.0]),
+    ("018", [686.0, 715.0, 734.0]),
+    ("017", [705.0, 738.0, 763.0]),
+    ("016", [742.0, 772.0, 796.0]),
+    ("015", [750.0, 791.0, 818.0]),
+    ("014", [757.0, 807.0, 838.0]),
+    ("013", [807.0, 837.0, 861.0]),
+    ("012", [843.0, 861.0, 882.0]),
+    ("011", [857.0, 875.0, 894.0]),
+    ("010", [886.0, 903.0, 919.0]),
+    ("09", [915.0, 920.0, 955.0]),
+    ("08", [922.0, 942.0, 983.0]),
+    ("07", [962.0, 976.0, 1008.0]),
+    ("06", [981.0, 998.0, 1013.0]),
+    ("05.5", [1004.0, 1015.0, 1025.0]),
+    ("05", [1021.0, 1031.0, 1044.0]),
+    ("04", [1046.0, 1063.0, 1077.0]),
+    ("03", [1071.0, 1086.0, 1104.0]),
+    ("02", [1078.0, 1102.0, 1122.0]),
+    ("01", [1093.0, 1119.0, 1138.0]),
+    ("1", [1109.0, 1137.0, 1154.0]),
+    ("2", [1112.0, 1142.0, 1164.0]),
+    ("3", [1115.0, 1152.0, 1170.0]),
+    ("4", [1141.0, 1162.0, 1183.0]),
+    ("5", [1159.0, 1186.0, 1207.0]),
+    ("5.5", [1167.0, 1203.0, 1225.0]),
+    ("6", [1185.0, 1222.0, 1243.0]),
+    ("7", [1201.0, 1239.0, 1257.0]),
+    ("8", [1211.0, 1249.0, 1271.0]),
+    ("9", [1224.0, 1260.0, 1280.0]),
+    ("10", [1251.0, 1285.0, 1305.0]),
+    ("11", [1272.0, 1294.0, 1315.0]),
+    ("12", [1285.0, 1306.0, 1326.0]),
+    ("13", [1310.0, 1331.0, 1348.0]),
+    ("14", [1351.0, 1365.0, 1384.0]),
+];
+
+/// Temperature, in C, `cone` bends at when the kiln climbs `rate` degrees per hour at the end.
+/// Between the charted rates the temperature goes with the logarithm of the rate, and outside them
+/// the nearest rate is used. Cones below 1 are written with a leading zero, like 06, and half
+/// cones as 5.5 or 5½.
+pub fn cone_temperature(cone: &str, rate: f64) -> Result<f64> {
+    let name = cone.replace('½', ".5");
+    let (_, temperatures) = CONES
+        .iter()
+        .find(|(cone, _)| *cone == name)
+        .ok_or_else(|| anyhow!("unknown cone: {}", cone))?;
+
+    let rate = rate.clamp(RATES[0], RATES[RATES.len() - 1]);
+    let upper = RATES
+        .iter()
+        .position(|charted| *charted >= rate)
+        .unwrap_or(RATES.len() - 1)
+        .max(1);
+    let lower = upper - 1;
+    let share = (rate / RATES[lower]).ln() / (RATES[upper] / RATES[lower]).ln();
+
+    Ok(temperatures[lower] + (temperatures[upper] - temperatures[lower]) * share)
+}
+
+#[cfg(test)]
+mod cone_tests {
+    use super::*;
+
+    #[test]
+    fn should_find_charted_temperatures() -> Result<()> {
+        assert_eq!(cone_temperature("6", 60.0)?, 1222.0);
+        assert_eq!(cone_temperature("06", 150.0)?, 1013.0);
+        assert_eq!(cone_temperature("019", 15.0)?, 656.0);
+        assert_eq!(cone_temperature("5½", 60.0)?, 1203.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_interpolate_between_rates() -> Result<()> {
+        let temperature = cone_temperature("10", 30.0)?;
+        assert!((temperature - 1268.0).abs() < 0.01, "was {}", temperature);
+
+        // Slower or faster than the chart goes, the nearest rate is used.
+        assert_eq!(cone_temperature("10", 5.0)?, 1251.0);
+        assert_eq!(cone_temperature("10", 500.0)?, 1305.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_unknown_cones() {
+        assert!(cone_temperature("42", 60.0).is_err());
+        assert!(cone_temperature("0", 60.0).is_err());
+    }
+}
@@ -9,9 +9,12 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tracing::trace;
 
+use super::cone::cone_temperature;
 use super::error::ScheduleError;
 
 const MAX_NAME_LENGTH: usize = 256;
+/// Temperature, in C, `ambient` stands for.
+const AMBIENT: f64 = 25.0;
 const RESERVED_CHARACTERS: &str = r#"[^-_.A-Za-z0-9]"#;
 const RESERVED_NAMES: &str = r#"(aux|clock\$|con|nul|prn|com[1-9]|lpt[1-9])(?:$|\.)"#;
 
@@ -19,7 +22,7 @@ const RESERVED_NAMES: &str = r#"(aux|clock\$|con|nul|prn|com[1-9]|lpt[1-9])(?:$|
 #[grammar = "schedule/step.pest"]
 struct StepParser;
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct NormalizedStep {
     pub start_time: u32,
     pub end_time: u32,
@@ -27,6 +30,32 @@ pub struct NormalizedStep {
     pub end_temperature: f64,
 }
 
+/// What a step does to the kiln's temperature.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum StepKind {
+    Heat,
+    Hold,
+    Cool,
+}
+
+impl NormalizedStep {
+    pub fn kind(&self) -> StepKind {
+        if self.end_temperature > self.start_temperature {
+            StepKind::Heat
+        } else if self.end_temperature < self.start_temperature {
+            StepKind::Cool
+        } else {
+            StepKind::Hold
+        }
+    }
+
+    /// Whether the kiln is held to the step's rate on the way down, heating as needed, and reports
+    /// when it can't cool that fast. True of descending ramps.
+    pub fn cooling(&self) -> bool {
+        self.kind() == StepKind::Cool
+    }
+}
+
 #[derive(Clone, Copy, Debug, Serialize)]
 pub enum TimeUnit {
     #[serde(alias = "Hour")]
@@ -84,7 +113,7 @@ impl std::str::FromStr for TemperatureScale {
 }
 
 /// Variant of the Schedule, but is normalized to cumulative seconds
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NormalizedSchedule {
     pub name: String,
     pub description: Option<String>,
@@ -154,7 +183,7 @@ fn temp_from_parsed(pairs: pest::iterators::Pairs<Rule>) -> Result<f64> {
 
     for r in pairs {
         match r.as_rule() {
-            Rule::ambient => temp = 25.0,
+            Rule::ambient => temp = AMBIENT,
             Rule::number => temp = r.as_str().parse::<f64>().unwrap(),
             Rule::scale => scale = TemperatureScale::from_str(r.as_str()).unwrap(),
             _ => temp = -1.0,
@@ -240,6 +269,50 @@ fn rate_from_parsed(
     })
 }
 
+/// A rate step up to a cone, which ends at the temperature the cone bends at for the step's rate.
+/// Without a starting temperature, it carries on from the step before. Cones are only fired up to,
+/// so a step that starts at or above the cone is an error.
+fn cone_rate_from_parsed(
+    pairs: pest::iterators::Pairs<Rule>,
+    previous_step: Option<NormalizedStep>,
+) -> Result<NormalizedStep> {
+    let mut start_temp = previous_step.map_or(AMBIENT, |s| s.end_temperature);
+    let mut cone = "";
+    let mut increment = 0.0;
+    let mut time_unit = TimeUnit::Seconds;
+
+    let prev = previous_step.unwrap_or_default();
+
+    for r in pairs {
+        match r.as_rule() {
+            Rule::from => start_temp = temp_from_parsed(r.into_inner())?,
+            Rule::cone => cone = r.as_str(),
+            Rule::increment => increment = r.into_inner().as_str().parse::<f32>()?,
+            Rule::time_unit => time_unit = TimeUnit::from_str(r.as_str())?,
+            _ => (),
+        }
+    }
+
+    let per_hour = increment as f64 * 3600.0 / (time_unit as u32) as f64;
+    let end_temp = cone_temperature(cone, per_hour)?;
+    if end_temp <= start_temp {
+        return Err(anyhow!(
+            "cone {} bends at {}C, no hotter than the {}C the step starts at",
+            cone,
+            end_temp,
+            start_temp
+        ));
+    }
+    let time = rate_to_seconds(&start_temp, &end_temp, increment, time_unit);
+
+    Ok(NormalizedStep {
+        start_time: prev.end_time,
+        end_time: prev.end_time + time,
+        start_temperature: start_temp,
+        end_temperature: end_temp,
+    })
+}
+
 fn parse_step(input: &str, prev: Option<NormalizedStep>) -> Result<NormalizedStep> {
     let parsed = StepParser::parse(Rule::step, input)?.next().unwrap();
 
@@ -247,6 +320,7 @@ fn parse_step(input: &str, prev: Option<NormalizedStep>) -> Result<NormalizedSte
         Rule::hold => hold_from_parsed(parsed.into_inner(), prev),
         Rule::duration => duration_from_parsed(parsed.into_inner(), prev),
         Rule::rate => rate_from_parsed(parsed.into_inner(), prev),
+        Rule::cone_rate => cone_rate_from_parsed(parsed.into_inner(), prev),
         _ => Err(anyhow!("unrecognized step provided: {}", input)),
     }
 }
@@ -286,8 +360,14 @@ impl Schedule {
 
     fn validate(schedule: &Schedule) -> Result<(), ScheduleError> {
         let _ = Schedule::to_filename(&schedule.name)?;
-        let step_validation: String = schedule
-            .steps
+
+        Schedule::validate_steps(&schedule.steps, 0)
+    }
+
+    /// Checks that every step can be parsed, and that together with the `kept` steps before them
+    ///   there are enough steps for a schedule.
+    pub fn validate_steps(steps: &[String], kept: usize) -> Result<(), ScheduleError> {
+        let step_validation: String = steps
             .iter()
             .filter_map(|s: &String| match parse_step(s.as_str(), None).err() {
                 // todo: serialize earlier, validate earlier
@@ -297,11 +377,11 @@ impl Schedule {
             .map(|s| s.into())
             .collect::<Vec<String>>()
             .join("\n");
-        let steps = &schedule.steps.len();
+        let steps = kept + steps.len();
 
-        if step_validation.len() == 0 && *steps >= 2 {
+        if step_validation.is_empty() && steps >= 2 {
             Ok(())
-        } else if *steps < 2 {
+        } else if steps < 2 {
             Err(ScheduleError::InvalidStep {
                 description: "not enough steps in schedule. more than 2 required".to_string(),
             })
@@ -471,6 +551,107 @@ impl NormalizedSchedule {
         }
     }
 
+    /// The hottest the schedule gets, to check against the kiln's maximum before firing it.
+    pub fn peak_temperature(&self) -> f64 {
+        self.steps
+            .iter()
+            .map(|s| s.start_temperature.max(s.end_temperature))
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    /// The latest time, no later than `before`, where the schedule climbs through the given
+    /// temperature. Used to pick a schedule up at the temperature the kiln is actually at.
+    pub fn time_at_temperature(&self, temperature: f64, before: u32) -> Option<u32> {
+        self.crossings(temperature)
+            .rev()
+            .find(|&time| time <= before)
+    }
+
+    /// The earliest time the schedule climbs through the given temperature. Used to start a
+    /// schedule on a kiln that's still warm.
+    pub fn first_time_at_temperature(&self, temperature: f64) -> Option<u32> {
+        self.crossings(temperature).next()
+    }
+
+    /// Every time the schedule climbs through the given temperature, in order.
+    fn crossings(&self, temperature: f64) -> impl DoubleEndedIterator<Item = u32> + '_ {
+        self.steps
+            .iter()
+            .filter(|s| s.end_temperature > s.start_temperature)
+            .filter(move |s| s.start_temperature <= temperature && temperature <= s.end_temperature)
+            .map(move |s| {
+                let fraction =
+                    (temperature - s.start_temperature) / (s.end_temperature - s.start_temperature);
+
+                s.start_time + (fraction * (s.end_time - s.start_time) as f64) as u32
+            })
+    }
+
+    /// Index of the step running at the given time. Where one step ends and the next begins, the
+    /// next step is the one running.
+    pub fn step_index(&self, time: u32) -> Option<usize> {
+        self.steps.iter().rposition(|s| s.start_time <= time)
+    }
+
+    /// How quickly the step running at `time` changes temperature, in degrees per second. Nothing
+    /// once the schedule's over.
+    pub fn ramp_rate(&self, time: u32) -> f64 {
+        if time >= self.total_duration() {
+            return 0.0;
+        }
+
+        match self.step_index(time).map(|index| &self.steps[index]) {
+            Some(step) if step.end_time > step.start_time => {
+                (step.end_temperature - step.start_temperature)
+                    / (step.end_time - step.start_time) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Replaces what's left of the schedule from `time` on with the given steps. The steps that
+    /// have already run are kept, and the step running at `time` is cut short there, so the new
+    /// steps start from the set point the kiln has reached and the clock carries on where it is.
+    /// Returns the index of the first new step.
+    pub fn replace_remaining(
+        &mut self,
+        time: u32,
+        steps: &[String],
+    ) -> Result<usize, ScheduleError> {
+        let current = self.step_index(time).unwrap_or(0);
+        let mut replaced = self.steps[..current].to_vec();
+
+        if let Some(step) = self.steps.get(current) {
+            if time > step.start_time && step.end_time > step.start_time {
+                let end_time = time.min(step.end_time);
+                let fraction =
+                    (end_time - step.start_time) as f64 / (step.end_time - step.start_time) as f64;
+
+                replaced.push(NormalizedStep {
+                    end_time,
+                    end_temperature: step.start_temperature
+                        + (step.end_temperature - step.start_temperature) * fraction,
+                    ..*step
+                });
+            }
+        }
+
+        let kept = replaced.len();
+        Schedule::validate_steps(steps, kept)?;
+
+        for step in steps {
+            let prev = replaced.last().copied();
+            let step = parse_step(step.as_str(), prev).map_err(|e| ScheduleError::InvalidStep {
+                description: format!("{:?}", e),
+            })?;
+
+            replaced.push(step);
+        }
+
+        self.steps = replaced;
+        Ok(kept)
+    }
+
     fn step_at_time(&self, time: u32) -> Option<&NormalizedStep> {
         let mut iter = self.steps.iter();
         let step = iter.find(|&&s| s.start_time <= time && time <= s.end_time);
@@ -544,7 +725,7 @@ mod parser_tests {
                 start_temperature: 25.0,
                 end_temperature: 200.0,
                 start_time: 0,
-                end_time: 7200
+                end_time: 7200,
             }
         );
 
@@ -556,7 +737,7 @@ mod parser_tests {
                 start_temperature: 100.0,
                 end_temperature: 300.0,
                 start_time: 0,
-                end_time: 30 * 60
+                end_time: 30 * 60,
             }
         );
 
@@ -588,6 +769,93 @@ mod parser_tests {
         Ok(())
     }
 
+    #[test]
+    fn should_mark_descending_ramps_as_cooling() -> Result<()> {
+        let input = "1000 to 700 by 50 per hour";
+        let output = parse_step(input, None)?;
+
+        assert_eq!(
+            output,
+            NormalizedStep {
+                start_temperature: 1000.0,
+                end_temperature: 700.0,
+                start_time: 0,
+                end_time: 6 * 60 * 60,
+            },
+            "input: [{}] failed",
+            input
+        );
+
+        assert!(output.cooling());
+        assert!(parse_step("1000 to 700 over 3 hours", None)?.cooling());
+        assert!(!parse_step("700 to 1000 over 3 hours", None)?.cooling());
+        assert!(!parse_step("hold for 1 hour", Some(output))?.cooling());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_rates_to_cones() -> Result<()> {
+        let input = "1100 to cone 6 by 60 degrees per hour";
+        let output = parse_step(input, None)?;
+
+        assert_eq!(
+            output,
+            NormalizedStep {
+                start_temperature: 1100.0,
+                end_temperature: 1222.0,
+                start_time: 0,
+                end_time: 7320,
+            },
+            "input: [{}] failed",
+            input
+        );
+
+        let input = "to cone 06 by 150 per hour";
+        let output = parse_step(input, None)?;
+        assert_eq!(output.start_temperature, 25.0);
+        assert_eq!(output.end_temperature, 1013.0);
+
+        assert!(parse_step("to cone 42 by 60 per hour", None).is_err());
+        // Already past the cone.
+        assert!(parse_step("1250 to cone 6 by 60 per hour", None).is_err());
+        assert!(parse_step("1222 to cone 6 by 60 per hour", None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_resolve_cones_when_normalizing() -> Result<()> {
+        let schedule = Schedule {
+            name: "glaze".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "ambient to 1100 by 150 per hour".to_string(),
+                "to cone 6 by 60 per hour".to_string(),
+                "hold for 10 minutes".to_string(),
+            ],
+        };
+        assert!(Schedule::validate(&schedule).is_ok());
+
+        let normalized = schedule.normalize()?;
+        assert_eq!(normalized.steps[1].start_temperature, 1100.0);
+        assert_eq!(normalized.steps[1].end_temperature, 1222.0);
+        assert_eq!(normalized.steps[2].end_temperature, 1222.0);
+        assert_eq!(normalized.peak_temperature(), 1222.0);
+
+        // Fast enough, the hotter cones bend past what most kilns are rated for.
+        let schedule = Schedule {
+            name: "stoneware".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec!["ambient to cone 10 by 150 per hour".to_string()],
+        };
+        assert_eq!(schedule.normalize()?.peak_temperature(), 1305.0);
+
+        Ok(())
+    }
+
     #[test]
     fn should_parse_string_to_duration() -> Result<()> {
         // let g = "(#|ambient) to (#|ambient) over # (hour|hours|minute|minutes|seconds)";
@@ -599,7 +867,7 @@ mod parser_tests {
                 start_temperature: 25.0,
                 end_temperature: 200.0,
                 start_time: 0,
-                end_time: 7200
+                end_time: 7200,
             }
         );
 
@@ -611,7 +879,7 @@ mod parser_tests {
                 start_temperature: 100.0,
                 end_temperature: 300.0,
                 start_time: 0,
-                end_time: 7200
+                end_time: 7200,
             }
         );
 
@@ -679,6 +947,153 @@ mod parser_tests {
         Ok(())
     }
 
+    #[test]
+    fn should_find_time_at_temperature() -> Result<()> {
+        let schedule = Schedule {
+            name: "test 1".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "0 to 100 over 1 hour".to_string(),
+                "hold for 1 hour".to_string(),
+                "100 to 50 over 1 hour".to_string(),
+                "50 to 200 over 1 hour".to_string(),
+            ],
+        };
+        let normalized = schedule.normalize()?;
+
+        // The latest climb through the temperature wins.
+        assert_eq!(
+            normalized.time_at_temperature(75.0, 4 * 3600),
+            Some(3 * 3600 + 600)
+        );
+        assert_eq!(normalized.time_at_temperature(75.0, 3 * 3600), Some(2700));
+
+        // Never later than the limit.
+        assert_eq!(normalized.time_at_temperature(75.0, 1800), None);
+
+        // Colder than anywhere in the schedule, or only reached while cooling.
+        assert_eq!(normalized.time_at_temperature(-10.0, 4 * 3600), None);
+        assert_eq!(normalized.time_at_temperature(150.0, 3 * 3600), None);
+
+        // The earliest climb through the temperature.
+        assert_eq!(normalized.first_time_at_temperature(75.0), Some(2700));
+        assert_eq!(
+            normalized.first_time_at_temperature(150.0),
+            Some(3 * 3600 + 2400)
+        );
+        assert_eq!(normalized.first_time_at_temperature(250.0), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_the_step_at_a_time() -> Result<()> {
+        let schedule = Schedule {
+            name: "test 1".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "0 to 100 over 1 hour".to_string(),
+                "hold for 1 hour".to_string(),
+            ],
+        };
+        let normalized = schedule.normalize()?;
+
+        assert_eq!(normalized.step_index(0), Some(0));
+        assert_eq!(normalized.step_index(1800), Some(0));
+        assert_eq!(normalized.step_index(3600), Some(1));
+        assert_eq!(normalized.step_index(7200), Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_the_ramp_rate_at_a_time() -> Result<()> {
+        let schedule = Schedule {
+            name: "test 1".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "0 to 360 over 1 hour".to_string(),
+                "hold for 1 hour".to_string(),
+                "360 to 0 over 2 hours".to_string(),
+            ],
+        };
+        let normalized = schedule.normalize()?;
+
+        assert_eq!(normalized.ramp_rate(0), 0.1);
+        assert_eq!(normalized.ramp_rate(3599), 0.1);
+        assert_eq!(normalized.ramp_rate(3600), 0.0);
+        assert_eq!(normalized.ramp_rate(7200), -0.05);
+        assert_eq!(normalized.ramp_rate(14400), 0.0);
+
+        let kinds: Vec<StepKind> = normalized.steps.iter().map(|step| step.kind()).collect();
+        assert_eq!(kinds, [StepKind::Heat, StepKind::Hold, StepKind::Cool]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_replace_remaining_steps() -> Result<()> {
+        let schedule = Schedule {
+            name: "test 1".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "0 to 100 over 1 hour".to_string(),
+                "hold for 1 hour".to_string(),
+                "100 to 50 over 1 hour".to_string(),
+            ],
+        };
+        let mut normalized = schedule.normalize()?;
+
+        // Halfway through the hold, hold for another 2 hours from now and skip the cooling.
+        let replaced = normalized.replace_remaining(5400, &["hold for 2 hours".to_string()])?;
+        assert_eq!(replaced, 2);
+        assert_eq!(normalized.steps.len(), 3);
+        assert_eq!(normalized.steps[1].end_time, 5400);
+        assert_eq!(normalized.total_duration(), 5400 + 2 * 3600);
+        assert_eq!(normalized.target_temperature(5400), 100.0);
+
+        // Steps that don't parse, or leave too few steps, are rejected untouched.
+        assert!(normalized
+            .replace_remaining(5400, &["hold until done".to_string()])
+            .is_err());
+        assert!(normalized
+            .replace_remaining(0, &["hold for 2 hours".to_string()])
+            .is_err());
+        assert_eq!(normalized.total_duration(), 5400 + 2 * 3600);
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_carry_on_from_the_set_point_when_replacing_mid_ramp() -> Result<()> {
+        let mut normalized = Schedule {
+            name: "test 1".to_string(),
+            description: None,
+            scale: TemperatureScale::Celsius,
+            steps: vec![
+                "0 to 100 over 1 hour".to_string(),
+                "hold for 1 hour".to_string(),
+            ],
+        }
+        .normalize()?;
+
+        // Halfway up the ramp, at 50C, hold there instead.
+        let replaced = normalized.replace_remaining(1800, &["hold for 1 hour".to_string()])?;
+        assert_eq!(replaced, 1);
+        assert_eq!(normalized.steps[0].end_time, 1800);
+        assert_eq!(normalized.steps[0].end_temperature, 50.0);
+        assert_eq!(normalized.target_temperature(900), 25.0);
+        assert_eq!(normalized.target_temperature(1800), 50.0);
+        assert_eq!(normalized.target_temperature(3000), 50.0);
+        assert_eq!(normalized.total_duration(), 1800 + 3600);
+
+        Ok(())
+    }
+
     #[test]
     fn should_create_and_validate_schedule_names() -> Result<()> {
         let name = Schedule::to_filename(&"name".to_string())?;
@@ -1,27 +1,139 @@
+use anyhow::Result;
+
+use crate::config::{Backend, Config};
+
+pub mod simulation;
 pub mod thermocouple;
+use simulation::ThermalModel;
+use thermocouple::ThermocoupleError;
 
 mod mcp9600;
-#[cfg(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-))]
 pub use mcp9600::real::MCP9600;
-
-#[cfg(not(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-)))]
-pub use mcp9600::simulated::MCP9600;
+pub use mcp9600::simulated::MCP9600 as SimulatedMCP9600;
 
 mod heater;
-#[cfg(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-))]
 pub use heater::real::Heater;
+pub use heater::simulated::Heater as SimulatedHeater;
+
+/// Source of the kiln's temperature, in celsius.
+pub trait TemperatureSensor: Send {
+    /// Temperature inside the kiln, at the hot junction.
+    fn read(&mut self) -> Result<f64, ThermocoupleError>;
+
+    /// Temperature of the sensor itself, at the cold junction.
+    fn read_internal(&mut self) -> Result<f64, ThermocoupleError>;
+}
+
+/// Switches the kiln's heating elements.
+pub trait HeaterOutput: Send {
+    fn on(&mut self);
+    fn off(&mut self);
+    fn toggle(&mut self);
+}
+
+/// Turns the heater it wraps off when dropped, so the elements are never left on after the
+/// controller stops, panics, or the process shuts down.
+pub struct HeaterGuard {
+    heater: Box<dyn HeaterOutput>,
+}
+
+impl HeaterGuard {
+    pub fn new(heater: Box<dyn HeaterOutput>) -> HeaterGuard {
+        HeaterGuard { heater }
+    }
+}
+
+impl HeaterOutput for HeaterGuard {
+    fn on(&mut self) {
+        self.heater.on()
+    }
+
+    fn off(&mut self) {
+        self.heater.off()
+    }
+
+    fn toggle(&mut self) {
+        self.heater.toggle()
+    }
+}
+
+impl Drop for HeaterGuard {
+    fn drop(&mut self) {
+        self.heater.off();
+    }
+}
+
+/// Creates the thermocouple and heater for the backend selected in the config. The heater is
+/// wrapped in a HeaterGuard.
+pub fn from_config(conf: &Config) -> Result<(Box<dyn TemperatureSensor>, Box<dyn HeaterOutput>)> {
+    match conf.backend {
+        Backend::Hardware => Ok((
+            Box::new(MCP9600::new(conf.thermocouple_address)?),
+            Box::new(HeaterGuard::new(Box::new(Heater::new(conf.gpio.heater)?))),
+        )),
+        Backend::Simulated => {
+            let model = ThermalModel::shared(conf.simulation.clone());
+
+            Ok((
+                Box::new(SimulatedMCP9600::with_model(
+                    conf.thermocouple_address,
+                    model.clone(),
+                )),
+                Box::new(HeaterGuard::new(Box::new(SimulatedHeater::with_model(
+                    conf.gpio.heater,
+                    model,
+                )))),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod sensor_tests {
+    use super::*;
+    use std::panic;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct MockHeater(Arc<AtomicBool>);
+
+    impl HeaterOutput for MockHeater {
+        fn on(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+
+        fn off(&mut self) {
+            self.0.store(false, Ordering::SeqCst);
+        }
+
+        fn toggle(&mut self) {
+            self.0.fetch_xor(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn should_turn_the_heater_off_when_dropped() {
+        let on = Arc::new(AtomicBool::new(false));
+        let mut heater = HeaterGuard::new(Box::new(MockHeater(on.clone())));
+
+        heater.on();
+        assert!(on.load(Ordering::SeqCst));
+
+        drop(heater);
+        assert!(!on.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_turn_the_heater_off_on_panic() {
+        let on = Arc::new(AtomicBool::new(false));
+        let mut heater = HeaterGuard::new(Box::new(MockHeater(on.clone())));
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(move || {
+            heater.on();
+            panic!("controller crashed with the heater on");
+        }));
 
-#[cfg(not(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-)))]
-pub use heater::simulated::Heater;
+        assert!(result.is_err());
+        assert!(!on.load(Ordering::SeqCst));
+    }
+}
@@ -1,6 +1,8 @@
 use std::error::Error;
 use tracing::debug;
 
+use crate::sensor::HeaterOutput;
+
 #[derive(Debug)]
 pub enum HeaterError {
     GpioError { source: rppal::gpio::Error },
@@ -22,10 +24,6 @@ impl std::fmt::Display for HeaterError {
     }
 }
 
-#[cfg(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-))]
 pub mod real {
     use super::*;
     use rppal::gpio::{Gpio, OutputPin};
@@ -39,51 +37,103 @@ pub mod real {
         /// The gpio pin to send the on/off signal. Note, this is the gpio index and
         ///   not the physical gpio pin. That is, GPIO #4 -> Physical pin #7.
         pub fn new(gpio_pin: u8) -> Result<Heater, HeaterError> {
-            let pin = Gpio::new()?.get(gpio_pin)?.into_output();
+            let mut pin = Gpio::new()?.get(gpio_pin)?.into_output();
+
+            // Left driven low when dropped, rather than reset to a floating input.
+            pin.set_low();
+            pin.set_reset_on_drop(false);
 
             Ok(Heater { pin: pin })
         }
 
         pub fn toggle(&mut self) {
-            &self.pin.toggle();
+            self.pin.toggle();
         }
 
         pub fn on(&mut self) {
-            &self.pin.set_high();
+            self.pin.set_high();
         }
 
         pub fn off(&mut self) {
-            &self.pin.set_low();
+            self.pin.set_low();
+        }
+    }
+
+    impl HeaterOutput for Heater {
+        fn on(&mut self) {
+            Heater::on(self)
+        }
+
+        fn off(&mut self) {
+            Heater::off(self)
+        }
+
+        fn toggle(&mut self) {
+            Heater::toggle(self)
         }
     }
 }
 
-#[cfg(not(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-)))]
 pub mod simulated {
     use super::*;
+    use crate::sensor::simulation::{SharedModel, SimulationConfig, ThermalModel};
 
     pub struct Heater {
         pin: u8,
+        model: SharedModel,
     }
 
     impl Heater {
+        /// Creates a heater warming its own kiln model.
         pub fn new(gpio_pin: u8) -> Result<Heater, HeaterError> {
-            Ok(Heater { pin: gpio_pin })
+            Ok(Heater::with_model(
+                gpio_pin,
+                ThermalModel::shared(SimulationConfig::default()),
+            ))
+        }
+
+        pub fn with_model(gpio_pin: u8, model: SharedModel) -> Heater {
+            Heater {
+                pin: gpio_pin,
+                model,
+            }
         }
 
         pub fn toggle(&mut self) {
             debug!("toggling pin [{}]", self.pin);
+            let mut model = self.model.lock().expect("unable to lock kiln model");
+            let on = !model.heater();
+            model.set_heater(on);
         }
 
         pub fn on(&mut self) {
             debug!("turning on pin [{}]", self.pin);
+            self.model
+                .lock()
+                .expect("unable to lock kiln model")
+                .set_heater(true);
         }
 
         pub fn off(&mut self) {
             debug!("turning off pin [{}]", self.pin);
+            self.model
+                .lock()
+                .expect("unable to lock kiln model")
+                .set_heater(false);
+        }
+    }
+
+    impl HeaterOutput for Heater {
+        fn on(&mut self) {
+            Heater::on(self)
+        }
+
+        fn off(&mut self) {
+            Heater::off(self)
+        }
+
+        fn toggle(&mut self) {
+            Heater::toggle(self)
         }
     }
 }
@@ -7,13 +7,14 @@
 ///   https://www.adafruit.com/product/4101
 ///
 use crate::sensor::thermocouple::ThermocoupleError;
+use crate::sensor::TemperatureSensor;
 
 // Registers
 const HOT_JUNCTION_TEMPERATURE: u8 = 0x00;
 // const JUNCTION_TEMPERATURE_DELTA: u8 = 0x01;
 const COLD_JUNCTION_TEMPERATURE: u8 = 0x02;
 // const RAW_DATA: u8 = 0x03;
-// const STATUS: u8 = 0x04;
+const STATUS: u8 = 0x04;
 // const SENSOR_CONFIGURATION: u8 = 0x05;
 // const DEVICE_CONFIGURATION: u8 = 0x06;
 // const ALERT_1_CONFIGURATION: u8 = 0x08;
@@ -39,11 +40,11 @@ const TOP_HALF_SIGN: u8 = 0x0F;
 // The Raw Data ADC register uses the first six bits of the upper byte as the sign.
 const _DATA_SIGN: u8 = 0x03;
 
-#[cfg(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-))]
-mod real {
+// Status register flags.
+const STATUS_SHORT_CIRCUIT: u8 = 0x20;
+const STATUS_INPUT_RANGE: u8 = 0x10;
+
+pub mod real {
     use super::*;
     use rppal::i2c::I2c;
     use tracing::error;
@@ -67,8 +68,14 @@ mod real {
             self.read_temperature(COLD_JUNCTION_TEMPERATURE, TOP_HALF_SIGN)
         }
 
+        /// Reads the hot junction, failing if the status register reports a thermocouple fault.
         pub fn read(&mut self) -> Result<f64, ThermocoupleError> {
-            self.read_temperature(HOT_JUNCTION_TEMPERATURE, FIRST_BIT_SIGN)
+            let temperature = self.read_temperature(HOT_JUNCTION_TEMPERATURE, FIRST_BIT_SIGN)?;
+
+            match status_error(self.read_status()?) {
+                Some(error) => Err(error),
+                None => Ok(temperature),
+            }
         }
 
         fn read_temperature(
@@ -78,16 +85,30 @@ mod real {
         ) -> Result<f64, ThermocoupleError> {
             let mut register = [0u8; 2];
 
+            self.read_register(junction, &mut register)?;
+            Ok(to_float(register, sign_bits))
+        }
+
+        fn read_status(&mut self) -> Result<u8, ThermocoupleError> {
+            let mut register = [0u8; 1];
+
+            self.read_register(STATUS, &mut register)?;
+            Ok(register[0])
+        }
+
+        fn read_register(
+            &mut self,
+            register: u8,
+            buffer: &mut [u8],
+        ) -> Result<(), ThermocoupleError> {
             let write_command: u8 = (self.address << 1) as u8;
             let read_command: u8 = write_command | 0x01;
 
-            self.i2c.block_write(write_command, &[junction])?;
+            self.i2c.block_write(write_command, &[register])?;
             self.i2c.write(&[read_command])?;
 
-            let result = self.i2c.block_read(junction, &mut register);
-
-            match result {
-                Ok(()) => Ok(to_float(register, sign_bits)),
+            match self.i2c.block_read(register, buffer) {
+                Ok(()) => Ok(()),
                 Err(error) => {
                     error!("error: {}", error);
                     Err(ThermocoupleError::I2CError { source: error })
@@ -95,54 +116,116 @@ mod real {
             }
         }
 
-        pub fn read_error(self) -> ThermocoupleError {
-            // TODO: Read status register and return errors.
-            ThermocoupleError::Unknown
+        /// The fault reported by the status register, or Unknown if it doesn't report one.
+        pub fn read_error(&mut self) -> ThermocoupleError {
+            match self.read_status() {
+                Ok(status) => status_error(status).unwrap_or(ThermocoupleError::Unknown),
+                Err(error) => error,
+            }
+        }
+    }
+
+    impl TemperatureSensor for MCP9600 {
+        fn read(&mut self) -> Result<f64, ThermocoupleError> {
+            MCP9600::read(self)
+        }
+
+        fn read_internal(&mut self) -> Result<f64, ThermocoupleError> {
+            MCP9600::read_internal(self)
         }
     }
 }
 
-#[cfg(not(any(
-    target = "armv7-unknown-linux-gnueabihf",
-    target = "arm-unknown-linux-gnueabihf"
-)))]
 pub mod simulated {
     use super::*;
-    use tracing::debug;
+    use crate::sensor::simulation::{SharedModel, SimulationConfig, ThermalModel};
+    use tracing::trace;
 
     pub struct MCP9600 {
         address: u16,
+        model: SharedModel,
     }
 
     impl MCP9600 {
+        /// Creates a thermocouple reading from its own, unheated, kiln model.
         pub fn new(address: u16) -> Result<Self, ThermocoupleError> {
-            Ok(MCP9600 { address })
+            Ok(MCP9600::with_model(
+                address,
+                ThermalModel::shared(SimulationConfig::default()),
+            ))
+        }
+
+        pub fn with_model(address: u16, model: SharedModel) -> Self {
+            MCP9600 { address, model }
         }
 
         pub fn read_internal(&mut self) -> Result<f64, ThermocoupleError> {
-            self.read_temperature(COLD_JUNCTION_TEMPERATURE, TOP_HALF_SIGN)
+            let temperature = self
+                .model
+                .lock()
+                .expect("unable to lock kiln model")
+                .ambient();
+            trace!(
+                address = self.address,
+                junction = COLD_JUNCTION_TEMPERATURE,
+                temperature
+            );
+
+            Ok(temperature)
         }
 
         pub fn read(&mut self) -> Result<f64, ThermocoupleError> {
-            self.read_temperature(HOT_JUNCTION_TEMPERATURE, FIRST_BIT_SIGN)
+            let temperature = self
+                .model
+                .lock()
+                .expect("unable to lock kiln model")
+                .temperature();
+            trace!(
+                address = self.address,
+                junction = HOT_JUNCTION_TEMPERATURE,
+                temperature
+            );
+
+            Ok(temperature)
         }
 
-        fn read_temperature(
-            &mut self,
-            junction: u8,
-            sign_bits: u8,
-        ) -> Result<f64, ThermocoupleError> {
-            debug!(junction, sign_bits, self.address);
-            Ok(0.0)
+        /// The simulated thermocouple never faults.
+        pub fn read_error(&mut self) -> ThermocoupleError {
+            ThermocoupleError::Unknown
         }
+    }
 
-        pub fn read_error(self) -> ThermocoupleError {
-            // TODO: Read status register and return errors.
-            ThermocoupleError::Unknown
+    impl TemperatureSensor for MCP9600 {
+        fn read(&mut self) -> Result<f64, ThermocoupleError> {
+            MCP9600::read(self)
+        }
+
+        fn read_internal(&mut self) -> Result<f64, ThermocoupleError> {
+            MCP9600::read_internal(self)
         }
     }
 }
 
+/// The thermocouple fault flagged in the status register, if any.
+///   See in the datasheet: REGISTER 5-6: STATUS
+///
+///   | bit 7 | bit 6 |  bit 5 |       bit 4 | bit 3 | bit 2 | bit 1 | bit 0 |
+///   |-------|-------|--------|-------------|-------|-------|-------|-------|
+///   | Burst | TH    | Short  | Input range | Alert | Alert | Alert | Alert |
+///   |  done | update| circuit| exceeded    |     4 |     3 |     2 |     1 |
+///
+/// The input goes out of range when the thermocouple is disconnected, so it's reported as an
+///   open circuit.
+fn status_error(status: u8) -> Option<ThermocoupleError> {
+    if status & STATUS_SHORT_CIRCUIT != 0 {
+        Some(ThermocoupleError::ShortCircuit)
+    } else if status & STATUS_INPUT_RANGE != 0 {
+        Some(ThermocoupleError::OpenCircuit)
+    } else {
+        None
+    }
+}
+
 /// Converts the two byte representation of the temperature to its floating point representation.
 ///   See in the datasheet: TABLE 5-1:SUMMARY OF REGISTERS AND BIT ASSIGNMENTS
 ///
@@ -262,3 +345,27 @@ mod to_float_tests {
 
     // TODO: Test every possible value...?
 }
+
+#[cfg(test)]
+mod status_tests {
+    use super::*;
+
+    #[test]
+    fn should_ignore_healthy_status() {
+        assert!(status_error(0b0000_0000).is_none());
+        // Burst complete, temperature updated and every alert raised.
+        assert!(status_error(0b1100_1111).is_none());
+    }
+
+    #[test]
+    fn should_read_circuit_faults() {
+        assert!(matches!(
+            status_error(0b0100_0000 | STATUS_SHORT_CIRCUIT),
+            Some(ThermocoupleError::ShortCircuit)
+        ));
+        assert!(matches!(
+            status_error(0b0100_0000 | STATUS_INPUT_RANGE),
+            Some(ThermocoupleError::OpenCircuit)
+        ));
+    }
+}
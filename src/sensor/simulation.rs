@@ -0,0 +1,187 @@
+//! A lumped thermal model of a kiln, shared between the simulated heater and thermocouple so
+//! that turning the heater on actually warms the simulated kiln.
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest interval, in seconds, integrated in a single step of the model.
+const MAX_STEP: f64 = 1.0;
+
+/// Physical parameters of the simulated kiln.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    /// Power of the heating elements, in watts.
+    pub heater_power: f64,
+    /// Energy needed to raise the kiln and its contents by one degree, in joules per celsius.
+    pub thermal_mass: f64,
+    /// Heat lost through the walls, in watts per degree above ambient.
+    pub heat_loss: f64,
+    /// Temperature of the room the kiln sits in, in celsius.
+    pub ambient: f64,
+    /// Time constant of the thermocouple trailing the kiln temperature, in seconds.
+    pub lag: f64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            heater_power: 5000.0,
+            thermal_mass: 40000.0,
+            heat_loss: 2.5,
+            ambient: 25.0,
+            lag: 30.0,
+        }
+    }
+}
+
+pub type SharedModel = Arc<Mutex<ThermalModel>>;
+
+#[derive(Debug)]
+pub struct ThermalModel {
+    config: SimulationConfig,
+    heater_on: bool,
+    kiln_temperature: f64,
+    sensor_temperature: f64,
+    last_sync: Instant,
+    realtime: bool,
+}
+
+impl ThermalModel {
+    /// Creates a model of a kiln at rest at the ambient temperature.
+    pub fn new(config: SimulationConfig) -> ThermalModel {
+        ThermalModel {
+            heater_on: false,
+            kiln_temperature: config.ambient,
+            sensor_temperature: config.ambient,
+            last_sync: Instant::now(),
+            realtime: true,
+            config,
+        }
+    }
+
+    /// Creates a model that only moves forward through `advance`, ignoring the wall clock.
+    pub fn accelerated(config: SimulationConfig) -> ThermalModel {
+        ThermalModel {
+            realtime: false,
+            ..ThermalModel::new(config)
+        }
+    }
+
+    pub fn shared(config: SimulationConfig) -> SharedModel {
+        Arc::new(Mutex::new(ThermalModel::new(config)))
+    }
+
+    /// Integrates the model forward by the given number of seconds.
+    pub fn advance(&mut self, seconds: f64) {
+        let mut remaining = seconds;
+
+        while remaining > 0.0 {
+            let dt = remaining.min(MAX_STEP);
+            let power = if self.heater_on {
+                self.config.heater_power
+            } else {
+                0.0
+            };
+            let loss = self.config.heat_loss * (self.kiln_temperature - self.config.ambient);
+
+            self.kiln_temperature += (power - loss) / self.config.thermal_mass * dt;
+
+            if self.config.lag > 0.0 {
+                let weight = (dt / self.config.lag).min(1.0);
+                self.sensor_temperature +=
+                    (self.kiln_temperature - self.sensor_temperature) * weight;
+            } else {
+                self.sensor_temperature = self.kiln_temperature;
+            }
+
+            remaining -= dt;
+        }
+    }
+
+    /// Advances the model by the wall-clock time since it was last synced.
+    pub fn sync(&mut self) {
+        if !self.realtime {
+            return;
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_sync).as_secs_f64();
+
+        self.last_sync = now;
+        self.advance(elapsed);
+    }
+
+    pub fn set_heater(&mut self, on: bool) {
+        self.sync();
+        self.heater_on = on;
+    }
+
+    pub fn heater(&self) -> bool {
+        self.heater_on
+    }
+
+    /// The temperature as seen by the thermocouple, in celsius.
+    pub fn temperature(&mut self) -> f64 {
+        self.sync();
+        self.sensor_temperature
+    }
+
+    pub fn ambient(&self) -> f64 {
+        self.config.ambient
+    }
+}
+
+#[cfg(test)]
+mod simulation_tests {
+    use super::*;
+
+    #[test]
+    fn starts_at_ambient() {
+        let mut model = ThermalModel::new(SimulationConfig::default());
+
+        model.advance(600.0);
+
+        assert_eq!(model.sensor_temperature, 25.0);
+    }
+
+    #[test]
+    fn heats_when_the_heater_is_on() {
+        let mut model = ThermalModel::new(SimulationConfig::default());
+
+        model.heater_on = true;
+        model.advance(600.0);
+
+        assert!(model.kiln_temperature > 25.0);
+        assert!(
+            model.sensor_temperature < model.kiln_temperature,
+            "thermocouple should lag behind the kiln"
+        );
+    }
+
+    #[test]
+    fn cools_towards_ambient_when_the_heater_is_off() {
+        let mut model = ThermalModel::new(SimulationConfig::default());
+
+        model.heater_on = true;
+        model.advance(3600.0);
+        let peak = model.sensor_temperature;
+
+        model.heater_on = false;
+        model.advance(3600.0);
+
+        assert!(model.sensor_temperature < peak);
+        assert!(model.sensor_temperature > 25.0);
+    }
+
+    #[test]
+    fn accelerated_models_ignore_the_wall_clock() {
+        let mut model = ThermalModel::accelerated(SimulationConfig::default());
+
+        model.set_heater(true);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        assert_eq!(model.temperature(), 25.0);
+    }
+}
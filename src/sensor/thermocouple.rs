@@ -1,3 +1,5 @@
+use std::error::Error;
+
 use rppal::i2c;
 
 #[derive(Debug)]
@@ -14,3 +16,19 @@ impl From<i2c::Error> for ThermocoupleError {
         ThermocoupleError::I2CError { source: error }
     }
 }
+
+impl Error for ThermocoupleError {}
+
+impl std::fmt::Display for ThermocoupleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ThermocoupleError::UnsupportedPlatform { message } => {
+                write!(f, "Unsupported platform {}", message)
+            }
+            ThermocoupleError::OpenCircuit => write!(f, "Thermocouple open circuit"),
+            ThermocoupleError::ShortCircuit => write!(f, "Thermocouple short circuit"),
+            ThermocoupleError::Unknown => write!(f, "Unknown thermocouple error"),
+            ThermocoupleError::I2CError { source } => write!(f, "I2C Error {}", source),
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use chrono::{DateTime, Utc};
 use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
+use crate::device::{Gains, PauseMode, StartFrom};
 use crate::schedule::NormalizedSchedule;
 
 #[derive(Debug)]
@@ -44,9 +46,47 @@ pub enum Command {
 
     Ping,
 
+    /// Starts the schedule straight away, or at the given time.
     StartSchedule {
         schedule: NormalizedSchedule,
+        from: StartFrom,
+        at: Option<DateTime<Utc>>,
     },
 
     StopSchedule,
+
+    PauseSchedule {
+        mode: PauseMode,
+    },
+
+    ResumeSchedule,
+
+    /// Jumps to the start of the step with the given index, or of the next step.
+    SkipStep {
+        step: Option<usize>,
+    },
+
+    /// Replaces the running schedule's steps from the current one on.
+    EditSchedule {
+        steps: Vec<String>,
+    },
+
+    /// Holds the kiln at a temperature in C, for a number of seconds or until stopped.
+    HoldTemperature {
+        temperature: f64,
+        timeout: Option<u32>,
+    },
+
+    /// Relay cycles the heater around a temperature in C to work out the PID gains.
+    Autotune {
+        temperature: f64,
+    },
+
+    /// Switches the kiln to the gains autotuning came up with.
+    ApplyGains,
+
+    /// Writes gains the kiln has switched to back to the config file.
+    SaveGains {
+        gains: Gains,
+    },
 }
@@ -1,15 +1,21 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
 use tokio::join;
-use tokio::sync::broadcast;
+use tokio::signal::unix::{signal, Signal, SignalKind};
 use tokio::sync::mpsc::{Sender, UnboundedSender};
-use tracing::{debug, error, instrument, trace};
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::timeout;
+use tracing::{debug, error, info, instrument, trace};
 use uuid::Uuid;
 
-use crate::config::Config;
-use crate::device::{Kiln, KilnEvent};
+use crate::config::{self, Config};
+use crate::device::{Kiln, KilnEvent, Watchdog};
+use crate::firing::PendingStart;
+use crate::sensor;
 use crate::server::log;
 use crate::server::{web, Command, Message, Monitor};
 
@@ -17,6 +23,9 @@ type SubscriptionList = Arc<Mutex<HashMap<String, Vec<(Uuid, UnboundedSender<Mes
 type ServiceList = Arc<Mutex<HashMap<String, Sender<Command>>>>;
 type ClientList = Arc<Mutex<HashMap<Uuid, UnboundedSender<Message>>>>;
 
+/// How long to wait for the kiln to turn the heater off when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone)]
 pub struct Manager {
     sender: broadcast::Sender<Command>,
@@ -39,13 +48,21 @@ impl Manager {
             })
             .init();
 
+        let terminate = signal(SignalKind::terminate())?;
         let web_service = web::start(conf.clone(), b_tx.clone());
+        let (thermocouple, heater) = sensor::from_config(&conf)?;
+        let watchdog = match &conf.watchdog {
+            Some(device) => Some(Watchdog::open(device)?),
+            None => None,
+        };
         let kiln = Kiln::start(
-            conf.thermocouple_address,
-            conf.gpio.heater,
+            thermocouple,
+            heater,
             conf.poll_interval,
             b_tx.clone(),
             conf.kiln,
+            conf.firings_folder.clone(),
+            watchdog,
         )
         .await?;
         let subscriptions = SubscriptionList::default();
@@ -54,15 +71,54 @@ impl Manager {
 
         let _monitor = Monitor::start(conf.web.keep_alive_interval, b_tx.clone());
 
+        let kiln_control = kiln.clone();
+        let config_file = conf.config_file.clone();
         let proc = tokio::task::spawn(async move {
-            let _ = Manager::process_commands(b_rx, subscriptions, services, clients, kiln).await;
+            let _ = Manager::process_commands(
+                b_rx,
+                subscriptions,
+                services,
+                clients,
+                kiln,
+                config_file,
+            )
+            .await;
         });
 
-        let _ = join!(proc, web_service);
+        tokio::select! {
+            _ = async { join!(proc, web_service) } => (),
+            _ = Manager::shutdown_signal(terminate) => {
+                info!("shutting down");
+                Manager::shutdown_kiln(&kiln_control).await;
+            }
+        }
 
         Ok(Manager { sender: b_tx })
     }
 
+    /// Resolves once the process is asked to stop, with SIGINT or SIGTERM.
+    async fn shutdown_signal(mut terminate: Signal) {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => (),
+            _ = terminate.recv() => (),
+        }
+    }
+
+    /// Waits for the kiln to turn the heater off, before the rest of the process goes down.
+    async fn shutdown_kiln(kiln: &Sender<KilnEvent>) {
+        let (done, stopped) = oneshot::channel();
+
+        if kiln.send(KilnEvent::Shutdown(done)).await.is_err() {
+            error!("kiln already stopped");
+            return;
+        }
+
+        match timeout(SHUTDOWN_TIMEOUT, stopped).await {
+            Ok(Ok(())) => info!("kiln shut down"),
+            _ => error!("kiln didn't confirm the heater is off"),
+        }
+    }
+
     #[instrument]
     async fn process_commands(
         mut receiver: broadcast::Receiver<Command>,
@@ -70,6 +126,7 @@ impl Manager {
         _services: ServiceList,
         clients: ClientList,
         kiln: Sender<KilnEvent>,
+        config_file: PathBuf,
     ) -> Result<()> {
         while let Ok(command) = receiver.recv().await {
             match command {
@@ -153,12 +210,59 @@ impl Manager {
                 }
                 Command::Ping => Manager::handle_ping(&clients),
                 Command::Unknown { input } => Manager::handle_unknown(Some(input)),
-                Command::StartSchedule { schedule } => {
-                    let _ = kiln.send(KilnEvent::Start(schedule)).await;
+                Command::StartSchedule {
+                    schedule,
+                    from,
+                    at: None,
+                } => {
+                    let _ = kiln.send(KilnEvent::Start(schedule, from)).await;
+                }
+                Command::StartSchedule {
+                    schedule,
+                    from,
+                    at: Some(at),
+                } => {
+                    let pending = PendingStart { schedule, from, at };
+                    let _ = kiln.send(KilnEvent::Wait(pending)).await;
                 }
                 Command::StopSchedule => {
                     let _ = kiln.send(KilnEvent::Stop).await;
                 }
+                Command::PauseSchedule { mode } => {
+                    let _ = kiln.send(KilnEvent::Pause(mode)).await;
+                }
+                Command::ResumeSchedule => {
+                    let _ = kiln.send(KilnEvent::Resume).await;
+                }
+                Command::SkipStep { step } => {
+                    let _ = kiln.send(KilnEvent::Skip(step)).await;
+                }
+                Command::EditSchedule { steps } => {
+                    let _ = kiln.send(KilnEvent::Edit(steps)).await;
+                }
+                Command::HoldTemperature {
+                    temperature,
+                    timeout,
+                } => {
+                    let _ = kiln
+                        .send(KilnEvent::Hold {
+                            temperature,
+                            timeout,
+                        })
+                        .await;
+                }
+                Command::Autotune { temperature } => {
+                    let _ = kiln.send(KilnEvent::Autotune(temperature)).await;
+                }
+                Command::ApplyGains => {
+                    let _ = kiln.send(KilnEvent::ApplyGains).await;
+                }
+                Command::SaveGains { gains } => {
+                    match config::save_gains(&config_file, &gains) {
+                        Ok(()) => info!(?gains, message = "saved gains to config"),
+                        Err(error) => error!("unable to save gains to config: {}", error),
+                    }
+                }
                 _ => Manager::handle_unknown(None),
             }
         }
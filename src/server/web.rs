@@ -7,6 +7,7 @@ use crate::server::Command;
 pub mod error;
 
 mod device;
+mod firings;
 mod schedules;
 mod sse;
 mod static_file;
@@ -18,9 +19,11 @@ pub async fn start(conf: Config, manager_sender: Sender<Command>) {
             .or(sse::routes(&manager_sender))
             .or(device::routes(
                 conf.schedules_folder.clone(),
+                conf.kiln.max_temp,
                 &manager_sender,
             ))
             .or(schedules::routes(conf.schedules_folder.clone()))
+            .or(firings::routes(conf.firings_folder.clone()))
             .or(steps::routes());
 
         warp::serve(routes)
@@ -1,3 +1,5 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
 use tokio::sync::broadcast::Sender;
 
 use warp::{
@@ -7,17 +9,69 @@ use warp::{
     Filter, Reply,
 };
 
+use crate::device::{PauseMode, StartFrom};
 use crate::schedule::{Schedule, ScheduleError};
 use crate::server::Command;
 
 use super::error::ErrorResponse;
 
-pub fn routes(directory: String, manager: &Sender<Command>) -> BoxedFilter<(impl Reply,)> {
+const LENGTH_LIMIT: u64 = 1024 * 32;
+
+#[derive(Debug, Deserialize)]
+struct StartQuery {
+    #[serde(default)]
+    from: StartFrom,
+    at: Option<DateTime<Utc>>,
+    delay: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PauseQuery {
+    #[serde(default)]
+    mode: PauseMode,
+}
+
+#[derive(Debug, Deserialize)]
+struct SkipQuery {
+    step: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HoldQuery {
+    temperature: f64,
+    timeout: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AutotuneQuery {
+    temperature: f64,
+}
+
+pub fn routes(
+    directory: String,
+    max_temp: f64,
+    manager: &Sender<Command>,
+) -> BoxedFilter<(impl Reply,)> {
     let dir = warp::any().map(move || directory.clone());
+    let max_temp = warp::any().map(move || max_temp);
     let m2 = manager.clone();
     let m3 = manager.clone();
+    let m4 = manager.clone();
+    let m5 = manager.clone();
+    let m6 = manager.clone();
+    let m7 = manager.clone();
+    let m8 = manager.clone();
+    let m9 = manager.clone();
+    let m10 = manager.clone();
     let manager2 = warp::any().map(move || m2.clone());
     let manager3 = warp::any().map(move || m3.clone());
+    let manager4 = warp::any().map(move || m4.clone());
+    let manager5 = warp::any().map(move || m5.clone());
+    let manager6 = warp::any().map(move || m6.clone());
+    let manager7 = warp::any().map(move || m7.clone());
+    let manager8 = warp::any().map(move || m8.clone());
+    let manager9 = warp::any().map(move || m9.clone());
+    let manager10 = warp::any().map(move || m10.clone());
 
     let start = warp::get()
         .and(dir.clone())
@@ -26,6 +80,8 @@ pub fn routes(directory: String, manager: &Sender<Command>) -> BoxedFilter<(impl
         .and(warp::path("kiln"))
         .and(warp::path::param())
         .and(warp::path("start"))
+        .and(max_temp)
+        .and(warp::query::<StartQuery>())
         .map(start);
 
     let stop = warp::get()
@@ -35,28 +91,137 @@ pub fn routes(directory: String, manager: &Sender<Command>) -> BoxedFilter<(impl
         .and(warp::path("stop"))
         .map(stop);
 
-    start.or(stop).boxed()
+    let pause = warp::get()
+        .and(manager4)
+        .and(warp::path("device"))
+        .and(warp::path("kiln"))
+        .and(warp::path("pause"))
+        .and(warp::query::<PauseQuery>())
+        .map(pause);
+
+    let resume = warp::get()
+        .and(manager5)
+        .and(warp::path("device"))
+        .and(warp::path("kiln"))
+        .and(warp::path("resume"))
+        .map(resume);
+
+    let skip = warp::get()
+        .and(manager6)
+        .and(warp::path("device"))
+        .and(warp::path("kiln"))
+        .and(warp::path("skip"))
+        .and(warp::query::<SkipQuery>())
+        .map(skip);
+
+    let edit = warp::put()
+        .and(manager7)
+        .and(warp::path("device"))
+        .and(warp::path("kiln"))
+        .and(warp::path("steps"))
+        .and(warp::body::content_length_limit(LENGTH_LIMIT))
+        .and(warp::body::json())
+        .map(edit);
+
+    let hold = warp::get()
+        .and(manager8)
+        .and(warp::path("device"))
+        .and(warp::path("kiln"))
+        .and(warp::path("hold"))
+        .and(max_temp)
+        .and(warp::query::<HoldQuery>())
+        .map(hold);
+
+    let autotune = warp::get()
+        .and(manager9)
+        .and(warp::path("device"))
+        .and(warp::path("kiln"))
+        .and(warp::path("autotune"))
+        .and(max_temp)
+        .and(warp::query::<AutotuneQuery>())
+        .map(autotune);
+
+    let apply_gains = warp::get()
+        .and(manager10)
+        .and(warp::path("device"))
+        .and(warp::path("kiln"))
+        .and(warp::path("gains"))
+        .and(warp::path("apply"))
+        .map(apply_gains);
+
+    start
+        .or(stop)
+        .or(pause)
+        .or(resume)
+        .or(skip)
+        .or(edit)
+        .or(hold)
+        .or(autotune)
+        .or(apply_gains)
+        .boxed()
 }
 
+/// Starts the named schedule. `/device/kiln/{name}/start?from=temperature` starts a warm kiln at
+///   the point the schedule reaches its temperature, rather than at the beginning. The start can
+///   be put off until a time, `?at=2021-01-01T02:00:00Z`, or by a number of seconds, `?delay=3600`.
+///   Schedules that get hotter than the kiln's `max_temp` are turned away.
 fn start(
     directory: String,
     manager: Sender<Command>,
     name: String,
+    max_temp: f64,
+    query: StartQuery,
 ) -> Result<Response<String>, http::Error> {
+    let at = match (query.at, query.delay) {
+        (Some(_), Some(_)) => {
+            return Response::builder().status(StatusCode::BAD_REQUEST).body(
+                ErrorResponse {
+                    message: "unable to start schedule".to_string(),
+                    error: "either a start time or a delay can be given, not both".to_string(),
+                }
+                .to_string(),
+            )
+        }
+        (at, None) => at,
+        (None, Some(delay)) => Some(Utc::now() + Duration::seconds(delay as i64)),
+    };
+
     match Schedule::by_name(&name, &directory) {
         Ok(s) => {
             let normalized = s.normalize();
 
             match normalized {
+                Ok(schedule) if schedule.peak_temperature() > max_temp => {
+                    Response::builder().status(StatusCode::BAD_REQUEST).body(
+                        ErrorResponse {
+                            message: format!("unable to start schedule with name [{}]", &name),
+                            error: format!(
+                                "schedule reaches {}, higher than the maximum of {}",
+                                schedule.peak_temperature(),
+                                max_temp
+                            ),
+                        }
+                        .to_string(),
+                    )
+                }
                 Ok(schedule) => {
                     manager
                         .clone()
-                        .send(Command::StartSchedule { schedule })
+                        .send(Command::StartSchedule {
+                            schedule,
+                            from: query.from,
+                            at,
+                        })
                         .expect("unable to send command to manager");
 
+                    let message = match at {
+                        Some(_) => r#"{ "message": "waiting" }"#,
+                        None => r#"{ "message": "started" }"#,
+                    };
+
                     Response::builder()
                         .status(StatusCode::OK)
-                        .body(r#"{ "message": "started" }"#.to_string())
+                        .body(message.to_string())
                 }
                 Err(error) => Response::builder()
                     .status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -102,3 +267,450 @@ fn stop(manager: Sender<Command>) -> Result<Response<String>, http::Error> {
         .status(StatusCode::OK)
         .body(r#"{ "message": "stopped" }"#.to_string())
 }
+
+/// Stops the schedule's clock, either holding the current set point or with the heater off.
+///   `/device/kiln/pause?mode=hold|off`, holding by default.
+fn pause(manager: Sender<Command>, query: PauseQuery) -> Result<Response<String>, http::Error> {
+    manager
+        .clone()
+        .send(Command::PauseSchedule { mode: query.mode })
+        .expect("unable to send command to manager");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(r#"{ "message": "paused" }"#.to_string())
+}
+
+fn resume(manager: Sender<Command>) -> Result<Response<String>, http::Error> {
+    manager
+        .clone()
+        .send(Command::ResumeSchedule)
+        .expect("unable to send command to manager");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(r#"{ "message": "resumed" }"#.to_string())
+}
+
+/// Moves the schedule's clock to the start of a step. `/device/kiln/skip` goes to the next step,
+///   `/device/kiln/skip?step=2` to the step with that index, counting from 0.
+fn skip(manager: Sender<Command>, query: SkipQuery) -> Result<Response<String>, http::Error> {
+    manager
+        .clone()
+        .send(Command::SkipStep { step: query.step })
+        .expect("unable to send command to manager");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(r#"{ "message": "skipped" }"#.to_string())
+}
+
+/// Replaces what's left of the running schedule with the list of steps in the body, starting now
+///   from the set point the kiln has reached. Steps are checked here, as if at least the first step
+///   had already been fired, and again by the kiln against what it has actually fired.
+fn edit(manager: Sender<Command>, steps: Vec<String>) -> Result<Response<String>, http::Error> {
+    if let Err(error) = Schedule::validate_steps(&steps, 1) {
+        return Response::builder().status(StatusCode::BAD_REQUEST).body(
+            ErrorResponse {
+                message: "unable to edit schedule".to_string(),
+                error: format!("{:?}", error),
+            }
+            .to_string(),
+        );
+    }
+
+    manager
+        .clone()
+        .send(Command::EditSchedule { steps })
+        .expect("unable to send command to manager");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(r#"{ "message": "edited" }"#.to_string())
+}
+
+/// Holds the kiln at a fixed temperature instead of running a schedule.
+///   `/device/kiln/hold?temperature=90&timeout=28800`, in C and seconds, holding until stopped when
+///   there's no timeout. Temperatures above the kiln's `max_temp` are turned away.
+fn hold(
+    manager: Sender<Command>,
+    max_temp: f64,
+    query: HoldQuery,
+) -> Result<Response<String>, http::Error> {
+    if !query.temperature.is_finite() || query.temperature > max_temp {
+        return Response::builder().status(StatusCode::BAD_REQUEST).body(
+            ErrorResponse {
+                message: "unable to hold temperature".to_string(),
+                error: format!("temperature must be a number no higher than {}", max_temp),
+            }
+            .to_string(),
+        );
+    }
+
+    manager
+        .clone()
+        .send(Command::HoldTemperature {
+            temperature: query.temperature,
+            timeout: query.timeout,
+        })
+        .expect("unable to send command to manager");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(r#"{ "message": "holding" }"#.to_string())
+}
+
+/// Relay cycles the heater around a temperature to work out the PID gains, which show up in the
+///   kiln's updates once it's done. `/device/kiln/autotune?temperature=500`, in C. Temperatures
+///   above the kiln's `max_temp` are turned away.
+fn autotune(
+    manager: Sender<Command>,
+    max_temp: f64,
+    query: AutotuneQuery,
+) -> Result<Response<String>, http::Error> {
+    if !query.temperature.is_finite() || query.temperature > max_temp {
+        return Response::builder().status(StatusCode::BAD_REQUEST).body(
+            ErrorResponse {
+                message: "unable to autotune".to_string(),
+                error: format!("temperature must be a number no higher than {}", max_temp),
+            }
+            .to_string(),
+        );
+    }
+
+    manager
+        .clone()
+        .send(Command::Autotune {
+            temperature: query.temperature,
+        })
+        .expect("unable to send command to manager");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(r#"{ "message": "tuning" }"#.to_string())
+}
+
+/// Switches the kiln to the gains from the last autotune, and writes them to the config file,
+///   unless the config has a gain schedule.
+fn apply_gains(manager: Sender<Command>) -> Result<Response<String>, http::Error> {
+    manager
+        .clone()
+        .send(Command::ApplyGains)
+        .expect("unable to send command to manager");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(r#"{ "message": "applying gains" }"#.to_string())
+}
+
+#[cfg(test)]
+mod route_tests {
+    use super::*;
+    use tokio::sync::broadcast;
+
+    use crate::config::DEFAULT_MAX_TEMP;
+
+    #[tokio::test]
+    async fn should_start_from_the_beginning_or_the_temperature() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes(
+            "./tests/sample_schedules".to_string(),
+            DEFAULT_MAX_TEMP,
+            &manager,
+        );
+
+        let response = warp::test::request()
+            .path("/device/kiln/valid/start")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::StartSchedule {
+                from: StartFrom::Beginning,
+                ..
+            })
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/valid/start?from=temperature")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::StartSchedule {
+                from: StartFrom::Temperature,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_refuse_schedules_hotter_than_the_kiln() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes("./tests/sample_schedules".to_string(), 1200.0, &manager);
+
+        // Cone 6 bends at 1222C.
+        let response = warp::test::request()
+            .path("/device/kiln/cone_6/start")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        let response = warp::test::request()
+            .path("/device/kiln/valid/start")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::StartSchedule { .. })
+        ));
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_put_off_the_start() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes(
+            "./tests/sample_schedules".to_string(),
+            DEFAULT_MAX_TEMP,
+            &manager,
+        );
+
+        let response = warp::test::request()
+            .path("/device/kiln/valid/start?at=2030-01-01T02:00:00Z")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        match commands.recv().await {
+            Ok(Command::StartSchedule { at: Some(at), .. }) => {
+                assert_eq!(at.to_rfc3339(), "2030-01-01T02:00:00+00:00")
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        let response = warp::test::request()
+            .path("/device/kiln/valid/start?delay=3600")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        match commands.recv().await {
+            Ok(Command::StartSchedule { at: Some(at), .. }) => {
+                let delay = (at - Utc::now()).num_seconds();
+                assert!(delay > 3590 && delay <= 3600, "delayed by {}", delay);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        let response = warp::test::request()
+            .path("/device/kiln/valid/start?delay=60&at=2030-01-01T02:00:00Z")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_pause_and_resume() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes("./schedules".to_string(), DEFAULT_MAX_TEMP, &manager);
+
+        let response = warp::test::request()
+            .path("/device/kiln/pause?mode=off")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::PauseSchedule {
+                mode: PauseMode::Off
+            })
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/pause")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::PauseSchedule {
+                mode: PauseMode::Hold
+            })
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/resume")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(commands.recv().await, Ok(Command::ResumeSchedule)));
+    }
+
+    #[tokio::test]
+    async fn should_reject_unknown_pause_modes() {
+        let (manager, _commands) = broadcast::channel(8);
+        let filter = routes("./schedules".to_string(), DEFAULT_MAX_TEMP, &manager);
+
+        let response = warp::test::request()
+            .path("/device/kiln/pause?mode=sideways")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_skip_steps() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes("./schedules".to_string(), DEFAULT_MAX_TEMP, &manager);
+
+        let response = warp::test::request()
+            .path("/device/kiln/skip")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::SkipStep { step: None })
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/skip?step=2")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::SkipStep { step: Some(2) })
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/skip?step=-1")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_edit_steps() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes("./schedules".to_string(), DEFAULT_MAX_TEMP, &manager);
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/device/kiln/steps")
+            .json(&vec!["hold for 2 hours", "1000 to 800 over 1 hour"])
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::EditSchedule { steps }) if steps.len() == 2
+        ));
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/device/kiln/steps")
+            .json(&vec!["hold until done"])
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/device/kiln/steps")
+            .json(&Vec::<String>::new())
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_hold_a_temperature() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes("./schedules".to_string(), DEFAULT_MAX_TEMP, &manager);
+
+        let response = warp::test::request()
+            .path("/device/kiln/hold?temperature=90&timeout=28800")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::HoldTemperature {
+                timeout: Some(28800),
+                ..
+            })
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/hold?temperature=90")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::HoldTemperature { timeout: None, .. })
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/hold")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        for temperature in &["NaN", "inf", "5000"] {
+            let response = warp::test::request()
+                .path(&format!("/device/kiln/hold?temperature={}", temperature))
+                .reply(&filter)
+                .await;
+            assert_eq!(response.status(), 400);
+        }
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_autotune_and_apply_the_gains() {
+        let (manager, mut commands) = broadcast::channel(8);
+        let filter = routes("./schedules".to_string(), DEFAULT_MAX_TEMP, &manager);
+
+        let response = warp::test::request()
+            .path("/device/kiln/autotune?temperature=500")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(
+            commands.recv().await,
+            Ok(Command::Autotune { temperature }) if temperature == 500.0
+        ));
+
+        let response = warp::test::request()
+            .path("/device/kiln/gains/apply")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(matches!(commands.recv().await, Ok(Command::ApplyGains)));
+
+        let response = warp::test::request()
+            .path("/device/kiln/autotune")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        for temperature in &["NaN", "inf", "5000"] {
+            let response = warp::test::request()
+                .path(&format!(
+                    "/device/kiln/autotune?temperature={}",
+                    temperature
+                ))
+                .reply(&filter)
+                .await;
+            assert_eq!(response.status(), 400);
+        }
+        assert!(commands.try_recv().is_err());
+    }
+}
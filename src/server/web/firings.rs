@@ -0,0 +1,231 @@
+use serde::Deserialize;
+use serde_json;
+use warp::{
+    filters::BoxedFilter,
+    http,
+    http::{Response, StatusCode},
+    Filter, Reply,
+};
+
+use super::error::ErrorResponse;
+use crate::firing::{export, ExportFormat, Firing, FiringError};
+
+const ROOT: &str = "firings";
+
+#[derive(Deserialize)]
+struct ExportParams {
+    pub format: Option<ExportFormat>,
+}
+
+pub fn routes(directory: String) -> BoxedFilter<(impl Reply,)> {
+    let dir = warp::any().map(move || directory.clone());
+
+    let firings = warp::get()
+        .and(dir.clone())
+        .and(warp::path(ROOT))
+        .and(warp::path::end())
+        .map(list);
+
+    let firing = warp::get()
+        .and(dir.clone())
+        .and(warp::path(ROOT))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .map(by_id);
+
+    let export = warp::get()
+        .and(dir.clone())
+        .and(warp::path(ROOT))
+        .and(warp::path::param())
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(warp::query::<ExportParams>())
+        .map(export_samples);
+
+    firings.or(firing).or(export).boxed()
+}
+
+fn list(directory: String) -> Result<Response<String>, http::Error> {
+    match Firing::all(&directory) {
+        Ok(firings) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&firings).unwrap()),
+        Err(error) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(
+                ErrorResponse {
+                    message: "unknown error listing firings".to_string(),
+                    error: format!("{:?}", error),
+                }
+                .to_string(),
+            ),
+    }
+}
+
+fn by_id(directory: String, id: String) -> Result<Response<String>, http::Error> {
+    match Firing::by_id(&id, &directory) {
+        Ok(record) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&record).unwrap()),
+        Err(error) => match error {
+            FiringError::IOError { description } => {
+                Response::builder().status(StatusCode::NOT_FOUND).body(
+                    ErrorResponse {
+                        message: format!("cannot find firing with id [{}]", &id),
+                        error: description,
+                    }
+                    .to_string(),
+                )
+            }
+            FiringError::InvalidId(_) => Response::builder().status(StatusCode::NOT_FOUND).body(
+                ErrorResponse {
+                    message: format!("cannot find firing with id [{}]", &id),
+                    error: format!("{}", error),
+                }
+                .to_string(),
+            ),
+            _ => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(
+                    ErrorResponse {
+                        message: "unknown error querying for firing".to_string(),
+                        error: format!("{:?}", error),
+                    }
+                    .to_string(),
+                ),
+        },
+    }
+}
+
+fn export_samples(
+    directory: String,
+    id: String,
+    params: ExportParams,
+) -> Result<Response<String>, http::Error> {
+    let format = params.format.unwrap_or(ExportFormat::Csv);
+    let mut body = Vec::new();
+
+    match Firing::by_id(&id, &directory).and_then(|r| export(&r.samples, format, &mut body)) {
+        Ok(()) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", format.content_type())
+            .header(
+                "content-disposition",
+                format!(r#"attachment; filename="{}.{}""#, id, format.extension()),
+            )
+            .body(String::from_utf8(body).unwrap()),
+        Err(FiringError::IOError { description }) => {
+            Response::builder().status(StatusCode::NOT_FOUND).body(
+                ErrorResponse {
+                    message: format!("cannot find firing with id [{}]", &id),
+                    error: description,
+                }
+                .to_string(),
+            )
+        }
+        Err(FiringError::InvalidId(description)) => {
+            Response::builder().status(StatusCode::NOT_FOUND).body(
+                ErrorResponse {
+                    message: format!("cannot find firing with id [{}]", &id),
+                    error: description,
+                }
+                .to_string(),
+            )
+        }
+        Err(error) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(
+                ErrorResponse {
+                    message: "unknown error exporting firing".to_string(),
+                    error: format!("{:?}", error),
+                }
+                .to_string(),
+            ),
+    }
+}
+
+#[cfg(test)]
+mod route_tests {
+    use super::*;
+    use crate::firing::Recorder;
+    use crate::schedule::Schedule;
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn should_list_firings() -> Result<()> {
+        let dir = tempdir()?;
+        let filter = routes(dir.path().to_str().unwrap().to_string());
+
+        let response = warp::test::request().path("/firings").reply(&filter).await;
+
+        dir.close()?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), "[]");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_get_firing_by_id() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap().to_string();
+        let schedule =
+            Schedule::from_file("./tests/sample_schedules/valid.yaml".to_string())?.normalize()?;
+        let id = Recorder::new(&directory).start(&schedule)?;
+        let filter = routes(directory);
+
+        let response = warp::test::request()
+            .path(format!("/firings/{}", id).as_str())
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let response = warp::test::request()
+            .path("/firings/definitely_doesnt_exist")
+            .reply(&filter)
+            .await;
+
+        dir.close()?;
+        assert_eq!(response.status(), 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_export_firing() -> Result<()> {
+        let dir = tempdir()?;
+        let directory = dir.path().to_str().unwrap().to_string();
+        let schedule =
+            Schedule::from_file("./tests/sample_schedules/valid.yaml".to_string())?.normalize()?;
+        let id = Recorder::new(&directory).start(&schedule)?;
+        let filter = routes(directory);
+
+        let response = warp::test::request()
+            .path(format!("/firings/{}/export", id).as_str())
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/csv");
+
+        let response = warp::test::request()
+            .path(format!("/firings/{}/export?format=json", id).as_str())
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+
+        let response = warp::test::request()
+            .path(format!("/firings/{}/export?format=xml", id).as_str())
+            .reply(&filter)
+            .await;
+
+        dir.close()?;
+        assert_eq!(response.status(), 400);
+
+        Ok(())
+    }
+}
@@ -7,6 +7,15 @@ fn works() {
     assert!(schedule.is_ok());
 }
 
+#[test]
+fn resolves_cones_to_temperatures() {
+    let filename = "./tests/sample_schedules/cone_6.yaml";
+    let schedule = Schedule::from_file(filename.to_string()).unwrap();
+    let normalized = schedule.normalize().unwrap();
+
+    assert_eq!(normalized.steps[1].end_temperature, 1222.0);
+}
+
 #[test]
 fn rejects_schedule_with_too_few_steps() {
     let filename = "./tests/sample_schedules/not_enough_steps.yaml";
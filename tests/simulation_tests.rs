@@ -0,0 +1,208 @@
+use caminatus::device::{simulate, simulate_autotune, Warning};
+use caminatus::schedule::{NormalizedSchedule, Schedule, TemperatureScale};
+use caminatus::sensor::simulation::SimulationConfig;
+use caminatus::{ControlStrategy, FeedForwardConfig, KilnConfig};
+
+fn config() -> KilnConfig {
+    KilnConfig::default()
+}
+
+/// One of the schedules in ./schedules, by file name without the extension.
+fn schedule(name: &str) -> NormalizedSchedule {
+    Schedule::from_file(format!("./schedules/{}.yaml", name))
+        .unwrap()
+        .normalize()
+        .unwrap()
+}
+
+#[test]
+fn runs_the_whole_schedule() {
+    let schedule = schedule("sample");
+    let total_duration = schedule.total_duration();
+
+    let trace = simulate(schedule, &config(), SimulationConfig::default(), 1000).unwrap();
+
+    assert_eq!(trace.first().unwrap().runtime, 0);
+    assert_eq!(trace.last().unwrap().runtime, total_duration);
+}
+
+#[test]
+fn tracks_the_set_point() {
+    let schedule = schedule("sample");
+
+    let trace = simulate(schedule, &config(), SimulationConfig::default(), 1000).unwrap();
+
+    for point in trace {
+        let error = (point.set_point - point.temperature).abs();
+        assert!(
+            error < config().max_difference as f64,
+            "{}C off the set point at {}s",
+            error,
+            point.runtime
+        );
+    }
+}
+
+#[test]
+fn tracks_the_set_point_with_fuzzy_control() {
+    let schedule = schedule("sample");
+    let fuzzy = KilnConfig {
+        controller: ControlStrategy::Fuzzy,
+        ..config()
+    };
+
+    let trace = simulate(schedule, &fuzzy, SimulationConfig::default(), 1000).unwrap();
+
+    for point in trace {
+        let error = (point.set_point - point.temperature).abs();
+        assert!(
+            error < fuzzy.max_difference as f64,
+            "{}C off the set point at {}s",
+            error,
+            point.runtime
+        );
+    }
+}
+
+#[test]
+fn feeds_forward_to_keep_up_with_ramps() {
+    let schedule = schedule("fast");
+    // Powerful enough to keep up with 50C a minute, with a thermocouple quick enough to tell, and
+    // gentle gains that lag on their own.
+    let simulation = SimulationConfig {
+        heater_power: 50_000.0,
+        lag: 5.0,
+        ..SimulationConfig::default()
+    };
+    let feedback = KilnConfig {
+        proportional: 0.05,
+        integral: 0.001,
+        derivative: 0.0,
+        ..config()
+    };
+    let ahead = KilnConfig {
+        feed_forward: FeedForwardConfig {
+            ramp: simulation.thermal_mass / simulation.heater_power / 3600.0,
+            loss: simulation.heat_loss / simulation.heater_power,
+            ambient: simulation.ambient,
+        },
+        ..feedback.clone()
+    };
+    // Up to the end of the hold, since there's no keeping up with cooling at 50C a minute.
+    let heated = schedule.steps[1].end_time;
+    let worst = |config: &KilnConfig| {
+        simulate(schedule.clone(), config, simulation.clone(), 1000)
+            .unwrap()
+            .iter()
+            .filter(|point| point.runtime <= heated)
+            .map(|point| (point.set_point - point.temperature).abs())
+            .fold(0.0, f64::max)
+    };
+
+    let lagging = worst(&feedback);
+    let keeping_up = worst(&ahead);
+    assert!(
+        keeping_up < lagging / 2.0,
+        "{}C off with feed forward, {}C without",
+        keeping_up,
+        lagging
+    );
+}
+
+#[test]
+fn cannot_outrun_the_heater() {
+    // 50C per minute is far quicker than the default simulated kiln can heat.
+    let schedule = schedule("fast");
+
+    let trace = simulate(schedule, &config(), SimulationConfig::default(), 1000).unwrap();
+    let peak = trace
+        .iter()
+        .map(|point| point.temperature)
+        .fold(f64::MIN, f64::max);
+
+    assert!(peak > 25.0);
+    assert!(peak < 100.0);
+}
+
+#[test]
+fn autotunes_gains_that_track_the_set_point() {
+    let gains = simulate_autotune(500.0, SimulationConfig::default(), 1000).unwrap();
+    let schedule = schedule("sample");
+    let tuned = KilnConfig {
+        proportional: gains.proportional,
+        integral: gains.integral,
+        derivative: gains.derivative,
+        ..config()
+    };
+
+    let trace = simulate(schedule, &tuned, SimulationConfig::default(), 1000).unwrap();
+
+    for point in trace {
+        let error = (point.set_point - point.temperature).abs();
+        assert!(
+            error < tuned.max_difference as f64,
+            "{}C off the set point at {}s with {:?}",
+            error,
+            point.runtime,
+            gains
+        );
+    }
+}
+
+/// Heats to 600C, holds, then cools to 450C at `rate` degrees per hour.
+fn cooling(rate: u32) -> NormalizedSchedule {
+    Schedule {
+        name: "slow cool".to_string(),
+        description: None,
+        scale: TemperatureScale::Celsius,
+        steps: vec![
+            "ambient to 600 by 250 per hour".to_string(),
+            "hold for 30 minutes".to_string(),
+            format!("600 to 450 by {} per hour", rate),
+        ],
+    }
+    .normalize()
+    .unwrap()
+}
+
+#[test]
+fn heats_to_cool_slower_than_the_kiln_would() {
+    let schedule = cooling(50);
+    let start = schedule.steps[2].start_time;
+
+    let trace = simulate(schedule, &config(), SimulationConfig::default(), 1000).unwrap();
+    let cooling: Vec<_> = trace
+        .iter()
+        .filter(|point| point.runtime >= start)
+        .collect();
+
+    // Left alone the kiln loses over 100C an hour at 600C.
+    assert!(cooling.iter().filter(|point| point.output > 0.0).count() > cooling.len() / 2);
+    for point in cooling {
+        let error = (point.set_point - point.temperature).abs();
+        assert!(
+            error < 2.0,
+            "{}C off the set point at {}s",
+            error,
+            point.runtime
+        );
+        assert_eq!(point.warning, None);
+    }
+}
+
+#[test]
+fn warns_when_the_kiln_cannot_cool_fast_enough() {
+    let schedule = cooling(300);
+    let start = schedule.steps[2].start_time;
+
+    let trace = simulate(schedule, &config(), SimulationConfig::default(), 1000).unwrap();
+
+    assert!(trace
+        .iter()
+        .filter(|point| point.runtime < start)
+        .all(|point| point.warning.is_none()));
+    assert!(trace.iter().any(|point| matches!(
+        point.warning,
+        Some(Warning::CannotCoolFastEnough { difference }) if difference > 50.0
+    )));
+}